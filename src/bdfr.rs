@@ -5,10 +5,128 @@
 // https://github.com/Serene-Arc/bulk-downloader-for-reddit
 
 use crate::models::ThingKind;
-use crate::utils::{format_selftext, Author, Comment, Flags, Flair, Media, Post, Preferences};
+use crate::utils::{format_num, format_selftext, time, Author, Comment, Flags, Flair, FlairPart, Media, Post, Preferences};
 
 use std::error::Error;
+use log::debug;
+use quick_xml::events::Event;
+use quick_xml::Reader;
 use serde::Deserialize;
+use serde_json::{Map, Value};
+use url::Url;
+
+/// Name BDFR gives the authors of deleted accounts
+const DELETED_AUTHOR: &str = "DELETED";
+
+/// Author name as Reddit shows it
+fn author_name(author: &str) -> String {
+    if author == DELETED_AUTHOR { "[deleted]" } else { author }.to_string()
+}
+
+/// Flair of a BDFR text field, like the live server's flairs without styles
+fn text_flair(text: Option<&str>) -> Flair {
+    let text = text.filter(|text| !text.is_empty());
+    Flair {
+        flair_parts: FlairPart::parse("text", None, text),
+        text: text.unwrap_or_default().to_string(),
+        ..Flair::default()
+    }
+}
+
+/// Fields of BDFR's XML archives that are not strings. `dict2xml` writes
+/// every value with Python's `str()`, like "True" or "None".
+const XML_BOOLS: [&str; 6] = ["over_18", "spoiler", "pinned", "locked", "stickied", "is_submitter"];
+const XML_INTEGERS: [&str; 2] = ["score", "num_comments"];
+const XML_FLOATS: [&str; 2] = ["upvote_ratio", "created_utc"];
+const XML_NULLABLE: [&str; 5] = ["distinguished", "link_flair_text", "author_flair", "subreddit", "edited"];
+/// Lists are written as one element per item, and not at all when empty
+const XML_LISTS: [&str; 2] = ["comments", "replies"];
+
+/// Read an archive BDFR wrote as XML into the shape of its JSON archives
+pub fn xml_to_json(xml: &str) -> Result<Value, Box<dyn Error>> {
+    /// An open element: its name, text and children
+    struct Element {
+        name: String,
+        text: String,
+        children: Map<String, Value>,
+    }
+
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().expand_empty_elements = true;
+    let mut stack: Vec<Element> = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => stack.push(Element {
+                name: String::from_utf8_lossy(start.local_name().as_ref()).to_string(),
+                text: String::new(),
+                children: Map::new(),
+            }),
+            Event::Text(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&text.unescape()?);
+                }
+            }
+            Event::CData(data) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&data.decode()?);
+                }
+            }
+            Event::End(_) => {
+                let element = stack.pop().ok_or("unexpected closing tag")?;
+                let value = if element.children.is_empty() { Value::String(element.text) } else { Value::Object(element.children) };
+                let Some(parent) = stack.last_mut() else {
+                    let Value::Object(mut entry) = value else {
+                        return Err("expected a post".into());
+                    };
+                    coerce_xml(&mut entry);
+                    return Ok(Value::Object(entry));
+                };
+                // Repeated elements are the items of a list
+                match parent.children.get_mut(&element.name) {
+                    Some(Value::Array(items)) => items.push(value),
+                    Some(item) => *item = Value::Array(vec![item.take(), value]),
+                    None => {
+                        parent.children.insert(element.name, value);
+                    }
+                }
+            }
+            Event::Eof => return Err("expected a post".into()),
+            _ => {}
+        }
+    }
+}
+
+/// Give the fields of a post or comment read from XML their JSON types
+fn coerce_xml(entry: &mut Map<String, Value>) {
+    for (key, value) in entry.iter_mut() {
+        let key = key.as_str();
+        let text = value.as_str().unwrap_or_default().trim().to_string();
+        if XML_LISTS.contains(&key) {
+            let mut items = match value.take() {
+                Value::Array(items) => items,
+                Value::Object(item) => vec![Value::Object(item)],
+                _ => Vec::new(),
+            };
+            for item in &mut items {
+                if let Some(item) = item.as_object_mut() {
+                    coerce_xml(item);
+                }
+            }
+            *value = Value::Array(items);
+        } else if XML_NULLABLE.contains(&key) && (text == "None" || text.is_empty()) {
+            *value = Value::Null;
+        } else if XML_BOOLS.contains(&key) || (key == "edited" && text == "False") {
+            *value = Value::Bool(text == "True");
+        } else if XML_INTEGERS.contains(&key) {
+            *value = text.parse::<i64>().map_or(Value::Null, Value::from);
+        } else if XML_FLOATS.contains(&key) || key == "edited" {
+            *value = text.parse::<f64>().map_or(Value::Null, Value::from);
+        }
+    }
+    // Empty lists are left out: the comments of posts, and replies to comments
+    let list = if entry.contains_key("submission") { "replies" } else { "comments" };
+    entry.entry(list).or_insert_with(|| Value::Array(Vec::new()));
+}
 
 /// BDFR representation of a Post (the original post in a Reddit thread).
 #[derive(Clone, Deserialize)]
@@ -31,7 +149,7 @@ pub struct SubmissionArchiveEntry {
     /// Post author's Reddit username
 	pub author: String,
     /// Flair text attached to the post
-	pub link_flair_text: String,
+	pub link_flair_text: Option<String>,
     /// Number of comments on the post, including replies to other comments
 	pub num_comments: i64,
     /// Whether the post is marked as NSFW
@@ -45,6 +163,9 @@ pub struct SubmissionArchiveEntry {
 	pub distinguished: Option<String>,
     /// Unix timestamp (UTC) of when the post was created
 	pub created_utc: f64,
+    /// Subreddit name, written by some BDFR versions
+	#[serde(default)]
+	pub subreddit: Option<String>,
     /// Post comments, threaded
 	pub comments: Vec<CommentArchiveEntry>,
 }
@@ -52,61 +173,144 @@ pub struct SubmissionArchiveEntry {
 impl SubmissionArchiveEntry {
     /// Convert to a Post (for PostTemplate)
     pub fn to_post(&self) -> Result<Post, Box<dyn Error>> {
-        println!("Creating Post from SubmissionArchiveEntry");
-        let media = Media {
-            url: self.url.clone(),
-            alt_url: self.url.clone(),
-            width: 0,
-            height: 0,
-            poster: self.author.clone(),
-            download_name: String::new(),
+        debug!("Creating Post from SubmissionArchiveEntry {}", self.id);
+        let (rel_time, created) = time(self.created_utc);
+        let is_self = self.is_self();
+        let media = if is_self {
+            Media::default()
+        } else {
+            Media {
+                url: self.url.clone(),
+                alt_url: self.url.clone(),
+                ..Media::default()
+            }
+        };
+        let community = self.community();
+        let domain = if is_self {
+            format!("self.{community}")
+        } else {
+            // Relative or malformed links have no domain
+            Url::parse(&self.url).ok().and_then(|url| Some(url.host_str()?.trim_start_matches("www.").to_string())).unwrap_or_default()
         };
-
-        let created = self.created_utc.to_string();
 
         Ok(Post {
             title: self.title.clone(),
-            ws_url: self.url.clone(),
             body: format_selftext(&self.selftext),
-            score: (self.score.to_string(), "".to_string()),
-            upvote_ratio: self.upvote_ratio as i64,
+            score: format_num(self.score),
+            upvote_ratio: (self.upvote_ratio * 100.0).round() as i64,
             permalink: self.permalink.clone(),
             id: self.id.clone(),
-            community: String::new(),
+            community,
             author: Author {
-                name: self.author.clone(),
+                name: author_name(&self.author),
                 flair: Flair::default(),
-                distinguished: String::new(),
+                distinguished: self.distinguished.clone().unwrap_or_default(),
             },
-            link_title: self.link_flair_text.clone(),
+            link_title: String::new(),
             poll: None,
-            post_type: "link".to_string(),
-            flair: Flair::default(),
+            post_type: if is_self { "self" } else { "link" }.to_string(),
+            flair: text_flair(self.link_flair_text.as_deref()),
             flags: Flags {
-                nsfw: self.over_18.clone(),
-                spoiler: self.spoiler.clone(),
-                stickied: self.pinned.clone(),
+                nsfw: self.over_18,
+                spoiler: self.spoiler,
+                stickied: self.pinned,
+                locked: self.locked,
             },
-            thumbnail: media.clone(),
-            media: media.clone(),
-            domain: self.url.clone(),
-            rel_time: created.clone(),
+            thumbnail: Media::default(),
+            media,
+            domain,
+            rel_time,
             created,
-            created_ts: self.created_utc.clone() as u64,
+            created_ts: self.created_utc.round() as u64,
             num_duplicates: 0,
-            comments: (String::new(), String::new()),
+            comments: format_num(self.num_comments),
             gallery: Vec::new(),
             awards: Vec::new(),
-            nsfw: self.over_18.clone(),
+            nsfw: self.over_18,
             out_url: None,
+            ws_url: String::new(),
         })
     }
 
+    /// Whether this is a text post, whose URL is the post itself
+    pub fn is_self(&self) -> bool {
+        self.url.is_empty() || (!self.permalink.is_empty() && self.url.split(['?', '#']).next().unwrap_or_default().ends_with(&self.permalink))
+    }
+
+    /// Subreddit name, taken from the permalink (e.g. "/r/rust/comments/...")
+    /// unless BDFR wrote it down, or from the comments
+    pub fn community(&self) -> String {
+        let from_permalink = || {
+            let path = Url::parse(&self.permalink).map_or_else(|_| self.permalink.clone(), |url| url.path().to_string());
+            path.strip_prefix("/r/").and_then(|rest| rest.split('/').next()).map(str::to_string)
+        };
+        self.subreddit
+            .clone()
+            .or_else(from_permalink)
+            .or_else(|| self.comments.iter().find_map(|comment| comment.subreddit.clone()))
+            .unwrap_or_default()
+    }
+
     pub fn comments(&self) -> Vec<Comment> {
-        self.comments.iter().map(|c| c.to_comment(&self)).collect()
+        self.comments.iter().map(|c| c.to_comment(self)).collect()
+    }
+
+    /// Stand-in for a submission that was not archived, linking to it on
+    /// Reddit, with comments that were archived on their own
+    pub fn placeholder(entries: Vec<CommentArchiveEntry>) -> Option<Self> {
+        let first = entries.iter().min_by(|a, b| a.created_utc.total_cmp(&b.created_utc))?;
+        let subreddit = first.subreddit.clone().unwrap_or_default();
+        let mut placeholder = Self {
+            title: first.submission_title.clone().unwrap_or_else(|| format!("Comments on {}", first.submission)),
+            name: format!("{}_{}", ThingKind::Link, first.submission),
+            url: format!("https://www.reddit.com/r/{subreddit}/comments/{}/", first.submission),
+            selftext: String::new(),
+            score: 0,
+            upvote_ratio: 0.0,
+            // The thread's date is unknown, it is that of the first comment
+            created_utc: first.created_utc,
+            permalink: String::new(),
+            id: first.submission.clone(),
+            author: String::new(),
+            link_flair_text: None,
+            num_comments: 0,
+            over_18: false,
+            spoiler: false,
+            pinned: false,
+            locked: false,
+            distinguished: None,
+            subreddit: Some(subreddit),
+            comments: Vec::new(),
+        };
+        placeholder.add_comments(entries);
+        placeholder.num_comments = placeholder.comments.iter().map(CommentArchiveEntry::count).sum::<usize>() as i64;
+        Some(placeholder)
+    }
+
+    /// Add comments that were archived on their own to the comment tree,
+    /// under their parent if it is in it. Comments already in it are skipped.
+    pub fn add_comments(&mut self, mut entries: Vec<CommentArchiveEntry>) {
+        // Parents before their replies
+        entries.sort_by(|a, b| a.created_utc.total_cmp(&b.created_utc));
+        for entry in entries {
+            if self.comments.iter().any(|comment| comment.find(&entry.id).is_some()) {
+                continue;
+            }
+            let parent = entry.parent_id.split_once('_').map_or("", |(_, id)| id);
+            match self.comments.iter_mut().find_map(|comment| comment.find_mut(parent)) {
+                Some(parent) => parent.replies.push(entry),
+                None => self.comments.push(entry),
+            }
+        }
     }
 }
 
+/// Whether a BDFR archive holds a comment that was archived on its own,
+/// rather than a submission
+pub fn is_comment_entry(json: &Value) -> bool {
+    json["submission"].is_string() && !json["comments"].is_array()
+}
+
 /// BDFR representation of a Comment (a reply in a Reddit thread).
 #[derive(Clone, Deserialize)]
 pub struct CommentArchiveEntry {
@@ -127,40 +331,70 @@ pub struct CommentArchiveEntry {
 	pub is_submitter: bool,
 	pub distinguished: Option<String>,
 	pub created_utc: f64,
+    /// Unix timestamp (UTC) of the last edit, or `false`, written by some
+    /// BDFR versions
+	#[serde(default)]
+	pub edited: Option<Value>,
+    /// Subreddit name
+	#[serde(default)]
+	pub subreddit: Option<String>,
     /// Fullname ID of the post or parent this is replying too (e.g. "t1_abcdef")
 	pub parent_id: String,
 	pub replies: Vec<CommentArchiveEntry>,
+    /// Title of the submission, written for comments archived on their own
+	#[serde(default)]
+	pub submission_title: Option<String>,
 }
 
 impl CommentArchiveEntry {
+    /// The comment with an ID, this one or one of its replies
+    fn find(&self, id: &str) -> Option<&Self> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.replies.iter().find_map(|reply| reply.find(id))
+    }
+
+    fn find_mut(&mut self, id: &str) -> Option<&mut Self> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.replies.iter_mut().find_map(|reply| reply.find_mut(id))
+    }
+
+    /// Number of comments in this comment's thread
+    fn count(&self) -> usize {
+        1 + self.replies.iter().map(Self::count).sum::<usize>()
+    }
+
     /// Convert to a Comment, for PostTemplate
     pub fn to_comment(&self, subm: &SubmissionArchiveEntry) -> Comment {
+        let (rel_time, created) = time(self.created_utc);
+        let distinguished = self.distinguished.clone().unwrap_or_default();
+
         Comment {
             id: self.id.clone(),
             kind: ThingKind::Comment.to_string(),
             parent_id: self.parent_id.clone(),
-            parent_kind: ThingKind::from_fullname(&self.parent_id).expect("parent_id must contain thing id").to_string(),
+            parent_kind: ThingKind::from_fullname(&self.parent_id).unwrap_or(ThingKind::Link).to_string(),
             post_link: subm.permalink.clone(),
-            post_author: subm.author.clone(),
-            body: self.body.clone(),
+            post_author: author_name(&subm.author),
+            body: format_selftext(&self.body),
             author: Author {
-                name: self.author.clone(),
-                flair: Flair {
-                    text: self.author_flair.clone().unwrap_or_else(|| String::new()),
-                    flair_parts: Vec::new(),
-                    background_color: String::new(),
-                    foreground_color: String::new(),
-                },
-                distinguished: self.distinguished.clone().unwrap_or(String::new()),
+                name: author_name(&self.author),
+                flair: text_flair(self.author_flair.as_deref()),
+                distinguished: distinguished.clone(),
             },
-            score: (self.score.to_string(), String::new()),
-            rel_time: strtime(self.created_utc as i64).unwrap_or_else(|_| self.created_utc.to_string()),
-            created: String::new(),
-            edited: (String::new(), String::new()),
-            replies: self.replies.iter().map(|reply| reply.to_comment(&subm)).collect(),
-            highlighted: self.stickied,
+            score: format_num(self.score),
+            rel_time,
+            created,
+            created_ts: self.created_utc.round() as u64,
+            edited: self.edited.as_ref().and_then(Value::as_f64).map_or((String::new(), String::new()), time),
+            replies: self.replies.iter().map(|reply| reply.to_comment(subm)).collect(),
+            highlighted: false,
             awards: Vec::new(),
-            collapsed: false,
+            // Like the live server, collapse stickied moderator comments
+            collapsed: distinguished == "moderator" && self.stickied,
             is_filtered: false,
             more_count: 0,
             prefs: Preferences::default(),
@@ -168,7 +402,125 @@ impl CommentArchiveEntry {
     }
 }
 
-fn strtime(timestamp: i64) -> Result<String, Box<dyn Error>> {
-    Ok(time::OffsetDateTime::from_unix_timestamp(timestamp)?
-        .format(&time::format_description::well_known::Rfc2822)?)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SELF_POST: &str = include_str!("../tests/fixtures/bdfr/self_post.json");
+    const LINK_POST: &str = include_str!("../tests/fixtures/bdfr/link_post.json");
+
+    /// Check a comment tree against the BDFR entries it was made from
+    fn assert_comments(entries: &Value, comments: &[Comment]) {
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), comments.len());
+        for (entry, comment) in entries.iter().zip(comments) {
+            assert_eq!(comment.id, entry["id"].as_str().unwrap());
+            assert_eq!(comment.parent_id, entry["parent_id"].as_str().unwrap());
+            assert_eq!(comment.parent_kind, &entry["parent_id"].as_str().unwrap()[..2]);
+            assert_eq!(comment.score.1, entry["score"].to_string());
+            assert_eq!(comment.created_ts, entry["created_utc"].as_f64().unwrap() as u64);
+            assert_eq!((comment.rel_time.clone(), comment.created.clone()), time(entry["created_utc"].as_f64().unwrap()));
+            assert_eq!(comment.author.flair.text, entry["author_flair"].as_str().unwrap_or_default());
+            assert_eq!(comment.author.distinguished, entry["distinguished"].as_str().unwrap_or_default());
+            assert_eq!(comment.body, format_selftext(entry["body"].as_str().unwrap()));
+            assert_comments(&entry["replies"], &comment.replies);
+        }
+    }
+
+    #[test]
+    fn test_self_post() {
+        let json: Value = serde_json::from_str(SELF_POST).unwrap();
+        let entry: SubmissionArchiveEntry = serde_json::from_value(json.clone()).unwrap();
+        let post = entry.to_post().unwrap();
+
+        assert_eq!(post.id, "1fjx2k9");
+        assert_eq!(post.title, json["title"].as_str().unwrap());
+        assert_eq!(post.community, "rust");
+        assert_eq!(post.post_type, "self");
+        assert_eq!(post.domain, "self.rust");
+        assert!(post.media.url.is_empty());
+        assert!(post.body.contains(r#"<a href="https://users.rust-lang.org/t/whats-everyone-working-on-this-week-38-2024/118245">rust-users</a>"#));
+        assert_eq!(post.score, format_num(24));
+        assert_eq!(post.upvote_ratio, 93);
+        assert_eq!(post.comments, format_num(4));
+        assert_eq!(post.author.name, "llogiq");
+        assert_eq!(post.author.distinguished, "moderator");
+        assert_eq!(post.flair.text, "🐝 activity megathread");
+        assert_eq!(post.flair.flair_parts.len(), 1);
+        assert!(post.flags.stickied && !post.flags.locked && !post.flags.nsfw && !post.flags.spoiler);
+        assert_eq!(post.created_ts, 1726470060);
+        assert_eq!((post.rel_time, post.created), time(1726470060.0));
+
+        let comments = entry.comments();
+        assert_comments(&json["comments"], &comments);
+        // Stickied moderator comments are collapsed
+        assert!(comments[0].collapsed && !comments[1].collapsed);
+        assert_eq!(comments[1].edited, time(1726479000.5));
+        assert_eq!(comments[1].replies[0].edited, (String::new(), String::new()));
+        assert_eq!(comments[1].replies[0].post_author, "llogiq");
+        assert_eq!(comments[2].author.name, "[deleted]");
+    }
+
+    #[test]
+    fn test_standalone_comments() {
+        let comment = |id: &str, submission: &str, parent: &str, created_utc: f64| -> CommentArchiveEntry {
+            serde_json::from_value(serde_json::json!({
+                "author": "ferris", "id": id, "score": 1, "subreddit": "golang", "author_flair": null, "submission": submission,
+                "stickied": false, "body": "hi", "is_submitter": false, "distinguished": null, "created_utc": created_utc,
+                "parent_id": parent, "replies": [], "submission_title": "Why Go?"
+            }))
+            .unwrap()
+        };
+        assert!(is_comment_entry(&serde_json::json!({"submission": "abc", "replies": []})));
+        assert!(!is_comment_entry(&serde_json::from_str(SELF_POST).unwrap()));
+
+        let mut entry: SubmissionArchiveEntry = serde_json::from_str(SELF_POST).unwrap();
+        entry.add_comments(vec![
+            comment("k2", "1fjx2k9", "t1_k1", 1726500000.0),
+            comment("k1", "1fjx2k9", "t1_lnsk3vd", 1726490000.0),
+            comment("lnu7q0a", "1fjx2k9", "t3_1fjx2k9", 1726501337.0),
+            comment("k3", "1fjx2k9", "t1_gone", 1726510000.0),
+        ]);
+        let comments = entry.comments();
+        assert_eq!(comments.len(), 4);
+        assert_eq!(comments[1].replies[1].id, "k1");
+        assert_eq!(comments[1].replies[1].replies[0].id, "k2");
+        assert_eq!(comments[3].id, "k3");
+
+        let placeholder = SubmissionArchiveEntry::placeholder(vec![comment("k5", "xyz789", "t1_k4", 2.0), comment("k4", "xyz789", "t3_xyz789", 1.0)]).unwrap();
+        let post = placeholder.to_post().unwrap();
+        assert_eq!((post.id.as_str(), post.title.as_str(), post.community.as_str()), ("xyz789", "Why Go?", "golang"));
+        assert_eq!(post.post_type, "link");
+        assert_eq!(post.media.url, "https://www.reddit.com/r/golang/comments/xyz789/");
+        assert_eq!(post.created_ts, 1);
+        assert_eq!(post.comments, format_num(2));
+        assert_eq!(placeholder.comments()[0].replies[0].id, "k5");
+        assert!(SubmissionArchiveEntry::placeholder(Vec::new()).is_none());
+        assert!(post.author.name.is_empty());
+    }
+
+    #[test]
+    fn test_link_post() {
+        let entry: SubmissionArchiveEntry = serde_json::from_str(LINK_POST).unwrap();
+        let post = entry.to_post().unwrap();
+
+        assert_eq!(post.post_type, "link");
+        assert_eq!(post.media.url, "https://blog.rust-lang.org/2024/09/05/Rust-1.81.0.html");
+        assert_eq!(post.domain, "blog.rust-lang.org");
+        assert!(post.body.is_empty());
+        assert_eq!(post.upvote_ratio, 99);
+        assert!(post.flair.text.is_empty() && post.flair.flair_parts.is_empty());
+        assert!(post.flags.locked && !post.flags.stickied);
+        assert!(post.author.distinguished.is_empty());
+        assert!(entry.comments().is_empty());
+
+        // A link that is not a URL does not fail the post
+        let entry = SubmissionArchiveEntry {
+            url: "/r/rust/wiki/faq".to_string(),
+            ..entry
+        };
+        let post = entry.to_post().unwrap();
+        assert_eq!(post.media.url, "/r/rust/wiki/faq");
+        assert!(post.domain.is_empty());
+    }
+}
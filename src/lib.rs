@@ -11,6 +11,20 @@ pub mod server;
 pub mod settings;
 pub mod models;
 pub mod ssg {
+    pub mod api;
+    pub mod browse;
+    pub mod config;
+    pub mod dump;
+    pub mod feed;
+    pub mod filter;
+    pub mod gdpr;
+    pub mod links;
+    pub mod manifest;
+    pub mod media;
+    pub mod preview;
+    pub mod search;
+    pub mod sitemap;
+    pub mod standalone;
     pub mod template;
     pub mod util;
     pub mod writer;
@@ -6,13 +6,26 @@ pub struct Fullname {
     pub id: String,
 }
 
+impl Fullname {
+    pub fn from_string(fullname: &str) -> Option<Self> {
+        let (kind, id) = fullname.split_once('_')?;
+        if id.is_empty() || id.contains('_') {
+            return None;
+        }
+        Some(Fullname {
+            kind: ThingKind::from_string(kind)?,
+            id: id.to_string(),
+        })
+    }
+}
+
 impl fmt::Display for Fullname {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}_{}", self.kind, self.id)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ThingKind {
     Listing,
     Comment,
@@ -98,10 +98,11 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 			}
 
 			let query = comment_query(&url);
+			let prefs = Preferences::new(&req);
 
 			let comments = match query.as_str() {
-				"" => parse_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &req),
-				_ => query_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &query, &req),
+				"" => parse_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &prefs),
+				_ => query_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &query, &prefs),
 			};
 
 			// Use the Post and Comment structs to generate a website to show users
@@ -110,7 +111,7 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 				post,
 				url_without_query: url.clone().trim_end_matches(&format!("?q={query}&type=comment")).to_string(),
 				sort,
-				prefs: Preferences::new(&req),
+				prefs,
 				single_thread,
 				url: req_url,
 				comment_query: query,
@@ -141,7 +142,8 @@ pub fn comment_query(url: &str) -> String {
 		.get("q").unwrap().clone().to_string()
 }
 
-fn parse_comments(json: &serde_json::Value, post_link: &str, post_author: &str, highlighted_comment: &str, filters: &HashSet<String>, req: &Request<Body>) -> Vec<Comment> {
+/// Parse the comment tree of a Reddit API listing (the second element of a `/comments/<id>.json` response).
+pub fn parse_comments(json: &serde_json::Value, post_link: &str, post_author: &str, highlighted_comment: &str, filters: &HashSet<String>, prefs: &Preferences) -> Vec<Comment> {
 	// Parse the comment JSON into a Vector of Comments
 	let comments = json["data"]["children"].as_array().map_or(Vec::new(), std::borrow::ToOwned::to_owned);
 
@@ -151,11 +153,11 @@ fn parse_comments(json: &serde_json::Value, post_link: &str, post_author: &str,
 		.map(|comment| {
 			let data = &comment["data"];
 			let replies: Vec<Comment> = if data["replies"].is_object() {
-				parse_comments(&data["replies"], post_link, post_author, highlighted_comment, filters, req)
+				parse_comments(&data["replies"], post_link, post_author, highlighted_comment, filters, prefs)
 			} else {
 				Vec::new()
 			};
-			build_comment(&comment, data, replies, post_link, post_author, highlighted_comment, filters, req)
+			build_comment(&comment, data, replies, post_link, post_author, highlighted_comment, filters, prefs)
 		})
 		.collect()
 }
@@ -167,7 +169,7 @@ fn query_comments(
 	highlighted_comment: &str,
 	filters: &HashSet<String>,
 	query: &str,
-	req: &Request<Body>,
+	prefs: &Preferences,
 ) -> Vec<Comment> {
 	let comments = json["data"]["children"].as_array().map_or(Vec::new(), std::borrow::ToOwned::to_owned);
 	let mut results = Vec::new();
@@ -177,10 +179,10 @@ fn query_comments(
 
 		// If this comment contains replies, handle those too
 		if data["replies"].is_object() {
-			results.append(&mut query_comments(&data["replies"], post_link, post_author, highlighted_comment, filters, query, req));
+			results.append(&mut query_comments(&data["replies"], post_link, post_author, highlighted_comment, filters, query, prefs));
 		}
 
-		let c = build_comment(&comment, data, Vec::new(), post_link, post_author, highlighted_comment, filters, req);
+		let c = build_comment(&comment, data, Vec::new(), post_link, post_author, highlighted_comment, filters, prefs);
 		if c.body.to_lowercase().contains(&query.to_lowercase()) {
 			results.push(c);
 		}
@@ -197,7 +199,7 @@ fn build_comment(
 	post_author: &str,
 	highlighted_comment: &str,
 	filters: &HashSet<String>,
-	req: &Request<Body>,
+	prefs: &Preferences,
 ) -> Comment {
 	let id = val(comment, "id");
 
@@ -272,6 +274,7 @@ fn build_comment(
 		},
 		rel_time,
 		created,
+		created_ts: unix_time.round() as u64,
 		edited,
 		replies,
 		highlighted,
@@ -279,7 +282,7 @@ fn build_comment(
 		collapsed,
 		is_filtered,
 		more_count,
-		prefs: Preferences::new(req),
+		prefs: prefs.clone(),
 	}
 }
 
@@ -310,6 +313,7 @@ mod tests {
 		let highlighted_comment = "abc123";
 		let filters = HashSet::new();
 		let req = Request::new(Body::empty());
+		let prefs = Preferences::new(&req);
 
 		// Test normal comment
 		let comment_data = json!({
@@ -340,7 +344,7 @@ mod tests {
 			post_author,
 			highlighted_comment,
 			&filters,
-			&req,
+			&prefs,
 		);
 
 		assert_eq!(comment.id, "comment1");
@@ -378,7 +382,7 @@ mod tests {
 			post_author,
 			highlighted_comment,
 			&filters,
-			&req,
+			&prefs,
 		);
 
 		assert!(comment.body.contains("[removed]"));
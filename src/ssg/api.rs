@@ -0,0 +1,347 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Read-only JSON API of a static archive, written next to its pages:
+//
+// - `api/index.json`: every post, newest first (`ApiIndex`)
+// - `api/posts/<page>.json`: a post and its comment tree (`ApiPost`), named
+//   after the post's page, as several inputs can hold the same post
+// - `api/users/<name>.json`: the posts and comments of an author (`ApiUser`)
+//
+// The schema is that of the structs below. Every file has a `version`, which
+// is raised whenever a field is removed or changes meaning. Links are
+// relative to the archive root, like the paths of the files.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::ssg::manifest::ManifestEntry;
+use crate::ssg::template::has_author_page;
+use crate::ssg::util::{comment_page_href, page_url};
+use crate::utils::{Comment, Post};
+
+/// Version of the schema
+pub const API_VERSION: u32 = 1;
+
+/// Index of the posts, relative to the archive root
+pub const API_INDEX_FILE: &str = "api/index.json";
+
+/// File of the post at `page`, relative to the archive root
+pub fn api_post_path(page: &Path) -> PathBuf {
+    Path::new("api/posts").join(page.with_extension("json"))
+}
+
+/// An author's file, relative to the archive root
+pub fn api_user_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("api/users/{name}.json"))
+}
+
+/// `api/index.json`
+#[derive(Serialize)]
+pub struct ApiIndex<'a> {
+    pub version: u32,
+    /// Title of the archive
+    pub title: &'a str,
+    /// Every post, newest first
+    pub posts: Vec<PostSummary>,
+}
+
+/// `api/posts/<page>.json`
+#[derive(Serialize)]
+pub struct ApiPost {
+    pub version: u32,
+    pub post: ApiPostDetail,
+    /// Top-level comments, in the order of the post's page
+    pub comments: Vec<ApiComment>,
+}
+
+/// `api/users/<name>.json`
+#[derive(Serialize)]
+pub struct ApiUser {
+    pub version: u32,
+    pub name: String,
+    /// Posts of the author, newest first
+    pub posts: Vec<PostSummary>,
+    /// Comments of the author, newest first
+    pub comments: Vec<CommentSummary>,
+}
+
+/// A post in a listing
+#[derive(Serialize)]
+pub struct PostSummary {
+    /// Reddit ID, without the `t3_` prefix
+    pub id: String,
+    pub title: String,
+    /// Subreddit name
+    pub subreddit: String,
+    pub author: String,
+    /// Unix timestamp
+    pub created_utc: u64,
+    pub score: i64,
+    pub num_comments: i64,
+    /// Link flair text, empty if none
+    pub flair: String,
+    /// "self", "link", "image", "video", "gif" or "gallery"
+    pub post_type: String,
+    pub nsfw: bool,
+    pub spoiler: bool,
+    /// The post's page
+    pub page: String,
+    /// The post's file, see `ApiPost`
+    pub json: String,
+}
+
+/// A post, with what its page shows besides its listing
+#[derive(Serialize)]
+pub struct ApiPostDetail {
+    #[serde(flatten)]
+    pub summary: PostSummary,
+    /// "moderator", "admin" or empty
+    pub distinguished: String,
+    /// HTML, empty if the post has no text
+    pub body: String,
+    /// Target of a link post, or the image or video of a media post. Media
+    /// that were archived along with the post are relative to the archive
+    /// root.
+    pub url: String,
+    /// Images of a gallery, in order
+    pub gallery: Vec<String>,
+    /// Website of `url`, or `self.<subreddit>` for text posts
+    pub domain: String,
+    /// Percentage of votes that were upvotes
+    pub upvote_ratio: i64,
+    pub stickied: bool,
+}
+
+/// A comment and its replies
+#[derive(Serialize)]
+pub struct ApiComment {
+    /// Reddit ID, without the `t1_` prefix
+    pub id: String,
+    pub author: String,
+    /// "moderator", "admin" or empty
+    pub distinguished: String,
+    /// HTML
+    pub body: String,
+    pub score: i64,
+    /// Unix timestamp
+    pub created_utc: u64,
+    /// The comment's permalink page
+    pub page: String,
+    pub replies: Vec<ApiComment>,
+    /// Replies that were not archived
+    pub more_replies: i64,
+}
+
+/// A comment in an author's listing
+#[derive(Serialize)]
+pub struct CommentSummary {
+    /// Reddit ID, without the `t1_` prefix
+    pub id: String,
+    /// ID of the post the comment belongs to
+    pub post_id: String,
+    pub post_title: String,
+    /// Subreddit name
+    pub subreddit: String,
+    /// HTML
+    pub body: String,
+    pub score: i64,
+    /// Unix timestamp
+    pub created_utc: u64,
+    /// The comment's permalink page
+    pub page: String,
+}
+
+/// Exact number of a formatted pair, like `Post::score`
+fn count(pair: &(String, String)) -> i64 {
+    pair.1.parse().unwrap_or_default()
+}
+
+impl PostSummary {
+    /// Summary of the post at `page`
+    pub fn new(post: &Post, page: &Path) -> Self {
+        Self {
+            id: post.id.clone(),
+            title: post.title.clone(),
+            subreddit: post.community.clone(),
+            author: post.author.name.clone(),
+            created_utc: post.created_ts,
+            score: count(&post.score),
+            num_comments: count(&post.comments),
+            flair: post.flair.text.clone(),
+            post_type: post.post_type.clone(),
+            nsfw: post.flags.nsfw,
+            spoiler: post.flags.spoiler,
+            page: post.permalink.clone(),
+            json: page_url("", &api_post_path(page)),
+        }
+    }
+}
+
+impl ApiPostDetail {
+    pub fn new(post: &Post, page: &Path) -> Self {
+        Self {
+            summary: PostSummary::new(post, page),
+            distinguished: post.author.distinguished.clone(),
+            body: post.body.clone(),
+            url: post.media.url.clone(),
+            gallery: post.gallery.iter().map(|media| media.url.clone()).collect(),
+            domain: post.domain.clone(),
+            upvote_ratio: post.upvote_ratio,
+            stickied: post.flags.stickied,
+        }
+    }
+}
+
+impl ApiComment {
+    /// Comment trees of the post at `page`, leaving out links to more replies
+    fn tree(page: &Path, comments: &[Comment]) -> Vec<Self> {
+        comments
+            .iter()
+            .filter(|comment| comment.kind == "t1")
+            .map(|comment| Self {
+                id: comment.id.clone(),
+                author: comment.author.name.clone(),
+                distinguished: comment.author.distinguished.clone(),
+                body: comment.body.clone(),
+                score: count(&comment.score),
+                created_utc: comment.created_ts,
+                page: comment_page_href(page, &comment.id),
+                replies: Self::tree(page, &comment.replies),
+                more_replies: comment.replies.iter().filter(|reply| reply.kind == "more").map(|reply| reply.more_count).sum(),
+            })
+            .collect()
+    }
+}
+
+/// The index of an archive
+pub fn api_index<'a>(title: &str, entries: impl IntoIterator<Item = &'a ManifestEntry>) -> Result<String, serde_json::Error> {
+    let mut posts: Vec<PostSummary> = entries.into_iter().map(|entry| PostSummary::new(&entry.post, &entry.output)).collect();
+    posts.sort_by(|a, b| b.created_utc.cmp(&a.created_utc).then_with(|| a.id.cmp(&b.id)));
+    serde_json::to_string(&ApiIndex {
+        version: API_VERSION,
+        title,
+        posts,
+    })
+}
+
+/// The file of the post at `page`
+pub fn api_post(post: &Post, page: &Path, comments: &[Comment]) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&ApiPost {
+        version: API_VERSION,
+        post: ApiPostDetail::new(post, page),
+        comments: ApiComment::tree(page, comments),
+    })
+}
+
+/// The listings of every author, by name
+pub fn api_users<'a>(entries: impl IntoIterator<Item = &'a ManifestEntry>) -> BTreeMap<String, ApiUser> {
+    fn user<'a>(users: &'a mut BTreeMap<String, ApiUser>, name: &str) -> &'a mut ApiUser {
+        users.entry(name.to_string()).or_insert_with(|| ApiUser {
+            version: API_VERSION,
+            name: name.to_string(),
+            posts: Vec::new(),
+            comments: Vec::new(),
+        })
+    }
+
+    let mut users = BTreeMap::new();
+    for entry in entries {
+        user(&mut users, &entry.post.author.name).posts.push(PostSummary::new(&entry.post, &entry.output));
+        for comment in &entry.comments {
+            user(&mut users, &comment.author.name).comments.push(CommentSummary {
+                id: comment.id.clone(),
+                post_id: entry.post.id.clone(),
+                post_title: entry.post.title.clone(),
+                subreddit: entry.post.community.clone(),
+                body: comment.body.clone(),
+                score: count(&comment.score),
+                created_utc: comment.created_ts,
+                page: comment.permalink.clone(),
+            });
+        }
+    }
+
+    users.retain(|name, _| has_author_page(name));
+    for user in users.values_mut() {
+        user.posts.sort_by(|a, b| b.created_utc.cmp(&a.created_utc).then_with(|| a.id.cmp(&b.id)));
+        user.comments.sort_by(|a, b| b.created_utc.cmp(&a.created_utc).then_with(|| a.id.cmp(&b.id)));
+    }
+    users
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssg::search::SearchEntry;
+    use crate::ssg::template::{comment_items, InputFormat};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_api_files() {
+        let (mut post, comments) = InputFormat::RedditJson
+            .json_decode(json!([
+                {"data": {"children": [{"kind": "t3", "data": {
+                    "id": "abc123",
+                    "title": "Hello archive",
+                    "subreddit": "rust",
+                    "author": "ferris",
+                    "score": 42,
+                    "num_comments": 3,
+                    "created_utc": 1600000000.0
+                }}]}},
+                {"data": {"children": [{"kind": "t1", "data": {
+                    "id": "c1",
+                    "author": "bob",
+                    "body_html": "<p>hi</p>",
+                    "score": 3,
+                    "created_utc": 1600000100.0,
+                    "parent_id": "t3_abc123",
+                    "replies": {"data": {"children": [{"kind": "more", "data": {"count": 2, "parent_id": "t1_c1", "children": ["c2", "c3"]}}]}}
+                }}]}}
+            ]))
+            .unwrap()
+            .thread()
+            .unwrap();
+        post.permalink = "rust/hello.html".to_string();
+
+        let file: Value = serde_json::from_str(&api_post(&post, Path::new("rust/hello.html"), &comments).unwrap()).unwrap();
+        assert_eq!(file["version"], API_VERSION);
+        assert_eq!(file["post"]["title"], "Hello archive");
+        assert_eq!(file["post"]["score"], 42);
+        assert_eq!(file["post"]["page"], "rust/hello.html");
+        assert_eq!(file["post"]["body"], "");
+        assert!(file["post"].get("flags").is_none());
+        assert_eq!(file["comments"][0]["page"], "comments/rust/hello/c1.html");
+        assert_eq!(file["comments"][0]["score"], 3);
+        assert_eq!(file["comments"][0]["more_replies"], 2);
+        assert_eq!(file["comments"][0]["replies"], json!([]));
+
+        let entry = ManifestEntry {
+            hash: String::new(),
+            output: PathBuf::from("rust/hello.html"),
+            media: Vec::new(),
+            media_hash: String::new(),
+            post: post.clone(),
+            comments: comment_items(&post, Path::new("rust/hello.html"), &comments),
+            search: SearchEntry::new(&post, &comments),
+            links: BTreeMap::new(),
+        };
+        let index: Value = serde_json::from_str(&api_index("Archive", [&entry]).unwrap()).unwrap();
+        assert_eq!(index["posts"][0]["score"], 42);
+        assert_eq!(index["posts"][0]["page"], "rust/hello.html");
+        assert_eq!(index["posts"][0]["json"], "api/posts/rust/hello.json");
+        assert_eq!(file["post"]["json"], index["posts"][0]["json"]);
+
+        let users = api_users([&entry]);
+        assert_eq!(users.keys().collect::<Vec<_>>(), vec!["bob", "ferris"]);
+        assert_eq!(users["bob"].comments[0].post_id, "abc123");
+        assert_eq!(users["bob"].comments[0].page, "comments/rust/hello/c1.html");
+        assert_eq!(users["ferris"].posts.len(), 1);
+        assert_eq!(api_user_path("bob"), PathBuf::from("api/users/bob.json"));
+        // Another input holding the same post gets a file of its own
+        assert_eq!(api_post_path(Path::new("golang/other.html")), PathBuf::from("api/posts/golang/other.json"));
+    }
+}
@@ -0,0 +1,305 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Pages for browsing a subreddit of a static archive by date (year, then
+// month) and by link flair, next to its sorted indexes.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use rinja::Template;
+use time::OffsetDateTime;
+
+use crate::ssg::template::subreddit_page_count;
+use crate::ssg::util::{archive_root, page_url, subreddit_page_href, subreddit_page_path};
+use crate::utils::{Post, Preferences};
+
+/// Directory of the date pages, relative to the subreddit's
+pub const DATE_DIR: &str = "date";
+
+/// Directory of the flair pages, relative to the subreddit's
+pub const FLAIR_DIR: &str = "flair";
+
+/// A page of a subreddit browsed by date or flair: links to narrower pages,
+/// posts, or both.
+#[derive(Template)]
+#[template(path = "archive_browse.html")]
+pub struct BrowseTemplate {
+    /// Subreddit name
+    pub sub: String,
+    /// `DATE_DIR` or `FLAIR_DIR`
+    pub section: String,
+    pub title: String,
+    pub description: String,
+    /// Labels and links of the enclosing pages, outermost first
+    pub crumbs: Vec<(String, String)>,
+    /// Labels and links of narrower pages, with their number of posts
+    pub links: Vec<(String, String, usize)>,
+    pub posts: Vec<Post>,
+    pub ends: (String, String),
+    pub prefs: Preferences,
+    pub url: String,
+}
+
+/// Name of a flair's directory. Like Reddit's flair search, flair is matched
+/// case-insensitively, and characters that are unsafe in paths are encoded.
+pub fn flair_slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars().flat_map(char::to_lowercase) {
+        match c {
+            c if c.is_alphanumeric() || c == '-' => slug.push(c),
+            ' ' => slug.push('-'),
+            c => slug.push_str(&format!("_{:x}", u32::from(c))),
+        }
+    }
+    slug
+}
+
+/// Link to the first page of a flair, relative to the archive root
+pub fn flair_href(sub: &str, text: &str) -> String {
+    subreddit_page_href(sub, &format!("{FLAIR_DIR}/{}", flair_slug(text)), 1)
+}
+
+/// Generate the date pages of a subreddit: its years, the months of each
+/// year and the paginated posts of each month. Posts are newest first.
+pub fn create_date_pages(sub: &str, posts: &[Post], page_size: usize, prefs: &Preferences) -> Vec<(PathBuf, BrowseTemplate)> {
+    let mut years: BTreeMap<i32, BTreeMap<u8, Vec<Post>>> = BTreeMap::new();
+    for post in posts {
+        let date = OffsetDateTime::from_unix_timestamp(post.created_ts as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        years.entry(date.year()).or_default().entry(date.month().into()).or_default().push(post.clone());
+    }
+
+    let page = Page { sub, section: DATE_DIR, prefs };
+    let root = (format!("r/{sub}"), subreddit_page_href(sub, "", 1));
+    let all_years = ("All years".to_string(), subreddit_page_href(sub, DATE_DIR, 1));
+
+    let mut pages = vec![page.index(
+        DATE_DIR,
+        format!("r/{sub}: posts by date"),
+        vec![root.clone()],
+        years
+            .iter()
+            .rev()
+            .map(|(year, months)| (year.to_string(), subreddit_page_href(sub, &format!("{DATE_DIR}/{year}"), 1), months.values().map(Vec::len).sum()))
+            .collect(),
+        posts.len(),
+    )];
+
+    for (year, months) in &years {
+        let year_dir = format!("{DATE_DIR}/{year}");
+        pages.push(page.index(
+            &year_dir,
+            format!("r/{sub}: posts from {year}"),
+            vec![root.clone(), all_years.clone()],
+            months
+                .iter()
+                .rev()
+                .map(|(month, posts)| (month_name(*month).to_string(), subreddit_page_href(sub, &format!("{year_dir}/{month:02}"), 1), posts.len()))
+                .collect(),
+            months.values().map(Vec::len).sum(),
+        ));
+
+        for (month, posts) in months {
+            pages.extend(page.listing(
+                &format!("{year_dir}/{month:02}"),
+                format!("r/{sub}: posts from {} {year}", month_name(*month)),
+                vec![root.clone(), all_years.clone(), (year.to_string(), subreddit_page_href(sub, &year_dir, 1))],
+                posts,
+                page_size,
+            ));
+        }
+    }
+
+    pages
+}
+
+/// Generate the flair pages of a subreddit: every flair and the paginated
+/// posts of each. Posts are newest first.
+pub fn create_flair_pages(sub: &str, posts: &[Post], page_size: usize, prefs: &Preferences) -> Vec<(PathBuf, BrowseTemplate)> {
+    let mut flairs: BTreeMap<String, Vec<Post>> = BTreeMap::new();
+    for post in posts.iter().filter(|post| !post.flair.text.trim().is_empty()) {
+        flairs.entry(flair_slug(&post.flair.text)).or_default().push(post.clone());
+    }
+
+    // Most used first
+    let mut flairs: Vec<(String, Vec<Post>)> = flairs.into_iter().collect();
+    flairs.sort_by_key(|(_, posts)| std::cmp::Reverse(posts.len()));
+
+    let page = Page { sub, section: FLAIR_DIR, prefs };
+    let root = (format!("r/{sub}"), subreddit_page_href(sub, "", 1));
+
+    let mut pages = vec![page.index(
+        FLAIR_DIR,
+        format!("r/{sub}: posts by flair"),
+        vec![root.clone()],
+        flairs.iter().map(|(_, posts)| (posts[0].flair.text.clone(), flair_href(sub, &posts[0].flair.text), posts.len())).collect(),
+        flairs.iter().map(|(_, posts)| posts.len()).sum(),
+    )];
+
+    let all_flairs = ("All flairs".to_string(), subreddit_page_href(sub, FLAIR_DIR, 1));
+    for (slug, posts) in &flairs {
+        pages.extend(page.listing(
+            &format!("{FLAIR_DIR}/{slug}"),
+            format!("r/{sub}: {}", posts[0].flair.text),
+            vec![root.clone(), all_flairs.clone()],
+            posts,
+            page_size,
+        ));
+    }
+
+    pages
+}
+
+/// Settings shared by the pages of one section of a subreddit
+struct Page<'a> {
+    sub: &'a str,
+    section: &'a str,
+    prefs: &'a Preferences,
+}
+
+impl Page<'_> {
+    fn template(&self, path: &Path, title: String, count: usize, crumbs: Vec<(String, String)>) -> BrowseTemplate {
+        BrowseTemplate {
+            sub: self.sub.to_string(),
+            section: self.section.to_string(),
+            title,
+            description: format!("{count} archived {}", if count == 1 { "post" } else { "posts" }),
+            crumbs,
+            links: Vec::new(),
+            posts: Vec::new(),
+            ends: (String::new(), String::new()),
+            prefs: Preferences {
+                archive_root: archive_root(path),
+                ..self.prefs.clone()
+            },
+            url: page_url(&self.prefs.archive_base_url, path),
+        }
+    }
+
+    /// A page linking to narrower pages
+    fn index(
+        &self,
+        dir: &str,
+        title: String,
+        crumbs: Vec<(String, String)>,
+        links: Vec<(String, String, usize)>,
+        count: usize,
+    ) -> (PathBuf, BrowseTemplate) {
+        let path = subreddit_page_path(self.sub, dir, 1);
+        let tmpl = BrowseTemplate {
+            links,
+            ..self.template(&path, title, count, crumbs)
+        };
+        (path, tmpl)
+    }
+
+    /// Paginated posts
+    fn listing(
+        &self,
+        dir: &str,
+        title: String,
+        crumbs: Vec<(String, String)>,
+        posts: &[Post],
+        page_size: usize,
+    ) -> Vec<(PathBuf, BrowseTemplate)> {
+        let page_count = subreddit_page_count(posts.len(), page_size);
+        posts
+            .chunks(page_size.max(1))
+            .enumerate()
+            .map(|(i, page_posts)| {
+                let page = i + 1;
+                let path = subreddit_page_path(self.sub, dir, page);
+                let prev = if page > 1 { subreddit_page_href(self.sub, dir, page - 1) } else { String::new() };
+                let next = if page < page_count { subreddit_page_href(self.sub, dir, page + 1) } else { String::new() };
+                let tmpl = BrowseTemplate {
+                    posts: page_posts.to_vec(),
+                    ends: (prev, next),
+                    ..self.template(&path, title.clone(), posts.len(), crumbs.clone())
+                };
+                (path, tmpl)
+            })
+            .collect()
+    }
+}
+
+fn month_name(month: u8) -> &'static str {
+    const MONTHS: [&str; 12] = [
+        "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December",
+    ];
+    MONTHS[usize::from(month.clamp(1, 12)) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post::PostTemplate;
+    use crate::utils::{parse_post, Flair, FlairPart};
+    use futures_lite::future::block_on;
+    use serde_json::json;
+
+    fn post(id: &str, created_utc: f64, flair: &str) -> Post {
+        let mut post = block_on(parse_post(&json!({"data": {
+            "id": id,
+            "subreddit": "rust",
+            "created_utc": created_utc,
+        }})));
+        post.flair = Flair {
+            text: flair.to_string(),
+            ..Flair::default()
+        };
+        post
+    }
+
+    #[test]
+    fn test_flair_slug() {
+        assert_eq!(flair_slug("Help"), "help");
+        assert_eq!(flair_slug(" Show and tell "), "show-and-tell");
+        assert_eq!(flair_slug("C++/Rust"), "c_2b_2b_2frust");
+        assert_eq!(flair_href("rust", "Help"), "r/rust/flair/help/index.html");
+    }
+
+    #[test]
+    fn test_browse_pages() {
+        // 2020-09-13, 2020-10-13 and 2021-01-01
+        let posts = vec![post("c", 1609459200.0, ""), post("b", 1602592000.0, "help"), post("a", 1600000000.0, "Help")];
+
+        let pages = create_date_pages("rust", &posts, 25, &Preferences::default());
+        let paths: Vec<&Path> = pages.iter().map(|(path, _)| path.as_path()).collect();
+        assert_eq!(paths, vec![
+            Path::new("r/rust/date/index.html"),
+            Path::new("r/rust/date/2020/index.html"),
+            Path::new("r/rust/date/2020/09/index.html"),
+            Path::new("r/rust/date/2020/10/index.html"),
+            Path::new("r/rust/date/2021/index.html"),
+            Path::new("r/rust/date/2021/01/index.html"),
+        ]);
+        assert_eq!(pages[0].1.links[0], ("2021".to_string(), "r/rust/date/2021/index.html".to_string(), 1));
+        assert_eq!(pages[1].1.links[0], ("October".to_string(), "r/rust/date/2020/10/index.html".to_string(), 1));
+        assert_eq!(pages[2].1.title, "r/rust: posts from September 2020");
+        assert_eq!(pages[2].1.crumbs.len(), 3);
+
+        let pages = create_flair_pages("rust", &posts, 1, &Preferences::default());
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].1.links, vec![("help".to_string(), "r/rust/flair/help/index.html".to_string(), 2)]);
+        assert_eq!(pages[2].0, PathBuf::from("r/rust/flair/help/2.html"));
+        assert_eq!(pages[2].1.ends.0, "r/rust/flair/help/index.html");
+    }
+
+    #[test]
+    fn test_flair_links() {
+        let prefs = Preferences {
+            archive_root: "../".to_string(),
+            ..Preferences::default()
+        };
+        let render = |community: &str| {
+            let mut post = post("abc123", 1600000000.0, "Help");
+            post.community = community.to_string();
+            post.flair.flair_parts = FlairPart::parse("text", None, Some("Help"));
+            PostTemplate::new(post, Vec::new(), "new".to_string(), prefs.clone(), false, String::new(), String::new()).render().unwrap()
+        };
+
+        // Posts without a community have no flair pages to link to
+        assert!(render("rust").contains("r/rust/flair/help/index.html"));
+        assert!(!render("").contains("/flair/"));
+    }
+}
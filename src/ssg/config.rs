@@ -0,0 +1,139 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Site config file for `ssg`, so that an archive can be rebuilt the same way
+// by anyone. Options given on the command line take precedence.
+
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::ssg::filter::ContentFilter;
+
+/// Layouts of the post lists, see `Preferences::layout`
+pub const LAYOUTS: [&str; 3] = ["card", "clean", "compact"];
+
+/// Comment orders that can be restored from an archive. Controversy is not
+/// archived, so that order is not available.
+pub const COMMENT_SORTS: [&str; 4] = ["confidence", "top", "new", "old"];
+
+#[derive(Default, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub base_url: Option<String>,
+    pub theme: Option<String>,
+    pub layout: Option<String>,
+    pub comment_sort: Option<String>,
+    pub page_size: Option<usize>,
+    pub show_nsfw: Option<bool>,
+    pub blur_nsfw: Option<bool>,
+    pub blur_spoiler: Option<bool>,
+    /// Mark links that leave the archive, see `LinkRewriter`
+    pub mark_external_links: bool,
+    /// Glob patterns of the input files to build, relative to the source
+    /// directory. Everything is built if empty.
+    pub include: Vec<String>,
+    /// Glob patterns of input files to leave out, even if included
+    pub exclude: Vec<String>,
+    pub filters: ContentFilter,
+}
+
+impl SiteConfig {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let config: Self = toml::from_str(&read_to_string(path)?).map_err(|e| format!("{}: {e}", path.display()))?;
+
+        if let Some(layout) = config.layout.as_ref().filter(|layout| !LAYOUTS.contains(&layout.as_str())) {
+            return Err(format!("unknown layout {layout:?}, expected one of: {}", LAYOUTS.join(", ")).into());
+        }
+        if let Some(sort) = config.comment_sort.as_ref().filter(|sort| !COMMENT_SORTS.contains(&sort.as_str())) {
+            return Err(format!("unknown comment sort {sort:?}, expected one of: {}", COMMENT_SORTS.join(", ")).into());
+        }
+
+        config.filters.validate()?;
+
+        Ok(config)
+    }
+
+    /// Which input files to build
+    pub fn input_filter(&self) -> Result<InputFilter, Box<dyn Error>> {
+        let globs = |patterns: &[String]| patterns.iter().map(|pattern| glob_regex(pattern)).collect::<Result<Vec<_>, _>>();
+        Ok(InputFilter {
+            include: globs(&self.include)?,
+            exclude: globs(&self.exclude)?,
+        })
+    }
+}
+
+/// Include and exclude rules for input files
+#[derive(Default)]
+pub struct InputFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl InputFilter {
+    /// Whether to build an input file, by its path relative to the source directory
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy().replace('\\', "/");
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.is_match(&path))) && !self.exclude.iter().any(|glob| glob.is_match(&path))
+    }
+}
+
+/// Translate a glob pattern: `*` and `?` match within a directory, `**`
+/// across directories.
+fn glob_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // `**/` also matches no directory at all
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_site_config() {
+        let config: SiteConfig = toml::from_str(
+            r#"
+            title = "Rust archive"
+            show_nsfw = false
+            include = ["rust/**"]
+            exclude = ["**/draft_*.json"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.title.as_deref(), Some("Rust archive"));
+        assert_eq!(config.show_nsfw, Some(false));
+        assert!(toml::from_str::<SiteConfig>("titel = \"typo\"").is_err());
+
+        let filter = config.input_filter().unwrap();
+        assert!(filter.matches(Path::new("rust/a.json")));
+        assert!(filter.matches(Path::new("rust/2024/a.json")));
+        assert!(!filter.matches(Path::new("rust/2024/draft_a.json")));
+        assert!(!filter.matches(Path::new("golang/a.json")));
+        assert!(InputFilter::default().matches(Path::new("golang/a.json")));
+    }
+}
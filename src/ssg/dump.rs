@@ -0,0 +1,303 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Pushshift and Arctic Shift dumps: newline-delimited JSON with one
+// submission or comment per line, usually zstd-compressed. Dumps are read a
+// line at a time. Comments are kept only for the submissions of the dumps,
+// with just the fields their pages are built from, and put back into trees
+// through their parent's fullname.
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use log::warn;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::models::{Fullname, ThingKind};
+use crate::ssg::manifest::hash_file;
+use crate::ssg::template::{PostTemplater, RedditThread, Thread, Threads};
+use crate::utils::format_selftext;
+
+/// Extensions of dump files
+pub const DUMP_EXTENSIONS: [&str; 3] = ["zst", "ndjson", "jsonl"];
+
+/// Largest window of the zstd frames of the dumps, which are compressed
+/// with `--long=31`
+const MAX_WINDOW_LOG: u32 = 31;
+
+/// Fields of a comment that its page is built from, see
+/// `post::build_comment()`. Dumps have many more, which are dropped.
+const COMMENT_FIELDS: [&str; 19] = [
+    "id",
+    "parent_id",
+    "author",
+    "author_flair_type",
+    "author_flair_richtext",
+    "author_flair_text",
+    "author_flair_background_color",
+    "author_flair_text_color",
+    "link_flair_text",
+    "distinguished",
+    "body",
+    "body_html",
+    "media_metadata",
+    "created_utc",
+    "edited",
+    "score",
+    "score_hidden",
+    "stickied",
+    "all_awardings",
+];
+
+/// Read a dump file a line at a time, decompressing it if needed.
+pub fn open(path: &Path) -> Result<Box<dyn BufRead>, Box<dyn Error>> {
+    let file = File::open(path)?;
+    if path.extension().is_some_and(|ext| ext == "zst") {
+        let mut decoder = zstd::Decoder::new(file)?;
+        decoder.window_log_max(MAX_WINDOW_LOG)?;
+        Ok(Box::new(BufReader::new(decoder)))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Objects of a dump, skipping blank lines
+pub fn objects(reader: impl BufRead) -> impl Iterator<Item = Result<Value, Box<dyn Error>>> {
+    reader.lines().filter_map(|line| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(serde_json::from_str(&line).map_err(Into::into)),
+        Err(e) => Some(Err(e.into())),
+    })
+}
+
+/// Whether a dump object is a comment rather than a submission
+fn is_comment(object: &Value) -> bool {
+    object["link_id"].is_string()
+}
+
+/// Whether a dump holds submissions. Like Pushshift's `RS_` and `RC_` files,
+/// dumps that start with a comment are taken to hold nothing else, so that
+/// they can be skipped without reading them through. Gathering the comments
+/// reads every dump, and warns about those that break this rule.
+fn holds_submissions(path: &Path) -> Result<bool, Box<dyn Error>> {
+    Ok(objects(open(path)?).next().transpose()?.is_some_and(|object| !is_comment(&object)))
+}
+
+/// ID of the thing a fullname like `t3_abc123` refers to, if it is of `kind`
+fn fullname_id(fullname: &Value, kind: ThingKind) -> Option<String> {
+    Fullname::from_string(fullname.as_str()?).filter(|fullname| fullname.kind == kind).map(|fullname| fullname.id)
+}
+
+/// The dumps of a build. Their comments are only gathered once a post has to
+/// be built, so that unchanged archives are not read twice.
+pub struct DumpIndex {
+    paths: Vec<PathBuf>,
+    /// Hash of every dump, see `fingerprint()`
+    fingerprint: String,
+    comments: OnceLock<Result<DumpComments, String>>,
+}
+
+/// What gathering the comments of a set of dumps found
+#[derive(Default)]
+struct DumpComments {
+    /// Comments of the submissions of the dumps, by the ID of their submission
+    threads: HashMap<String, Vec<Value>>,
+    /// Dumps that start with a comment but hold submissions, which are skipped
+    skipped: Vec<PathBuf>,
+}
+
+impl DumpIndex {
+    pub fn new(paths: &[PathBuf]) -> Result<Self, Box<dyn Error>> {
+        let mut hasher = Sha256::new();
+        for path in paths {
+            hasher.update(hash_file(path)?);
+        }
+        Ok(Self {
+            paths: paths.to_vec(),
+            fingerprint: format!("{:x}", hasher.finalize()),
+            comments: OnceLock::new(),
+        })
+    }
+
+    /// Hash of every dump. Comments can be in any dump, so a change to one of
+    /// them can change the threads of all the others.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    fn comments(&self) -> Result<&DumpComments, Box<dyn Error>> {
+        self.comments.get_or_init(|| scan(&self.paths).map_err(|e| e.to_string())).as_ref().map_err(|e| e.clone().into())
+    }
+
+    /// Submissions of a dump and their comment trees, built as they are read
+    pub fn threads(&self, path: &Path) -> Result<Threads<'_>, Box<dyn Error>> {
+        if !holds_submissions(path)? {
+            return Ok(Box::new(iter::empty()));
+        }
+        let comments = &self.comments()?.threads;
+
+        Ok(Box::new(objects(open(path)?).filter_map(move |object| match object {
+            Ok(object) if is_comment(&object) => None,
+            Ok(object) => {
+                let id = object["id"].as_str().unwrap_or_default().to_string();
+                Some(thread(object, comments.get(&id).map_or(&[], Vec::as_slice)))
+            }
+            Err(e) => Some(Err(e)),
+        })))
+    }
+}
+
+/// Gather the comments of the submissions of every dump: submissions are
+/// looked up first, so that the comments of other threads can be skipped.
+fn scan(paths: &[PathBuf]) -> Result<DumpComments, Box<dyn Error>> {
+    let mut posts = HashSet::new();
+    let mut submission_dumps = HashSet::new();
+    for path in paths {
+        if !holds_submissions(path)? {
+            continue;
+        }
+        submission_dumps.insert(path);
+        for object in objects(open(path)?) {
+            let object = object?;
+            if !is_comment(&object) {
+                posts.insert(object["id"].as_str().unwrap_or_default().to_string());
+            }
+        }
+    }
+
+    let mut comments = DumpComments::default();
+
+    for path in paths {
+        for object in objects(open(path)?) {
+            let object = object?;
+            if let Some(post) = fullname_id(&object["link_id"], ThingKind::Link).filter(|post| posts.contains(post)) {
+                comments.threads.entry(post).or_default().push(comment_fields(object));
+            } else if !is_comment(&object) && !submission_dumps.contains(path) && !comments.skipped.contains(path) {
+                warn!("{} starts with a comment, so its submissions are skipped; keep submissions and comments in separate dumps", path.display());
+                comments.skipped.push(path.clone());
+            }
+        }
+    }
+    Ok(comments)
+}
+
+/// Keep only the fields of a comment that its page is built from
+fn comment_fields(mut comment: Value) -> Value {
+    if let Value::Object(fields) = &mut comment {
+        fields.retain(|key, _| COMMENT_FIELDS.contains(&key.as_str()));
+    }
+    comment
+}
+
+/// A submission and its comments as a thread of Reddit's API, so that they
+/// are parsed like any other.
+fn thread(mut submission: Value, comments: &[Value]) -> Result<Thread, Box<dyn Error>> {
+    // Dumps only have the markdown of texts
+    if submission["selftext_html"].is_null() {
+        submission["selftext_html"] = Value::from(format_selftext(submission["selftext"].as_str().unwrap_or_default()));
+    }
+
+    let post = Fullname {
+        kind: ThingKind::Link,
+        id: submission["id"].as_str().unwrap_or_default().to_string(),
+    };
+    let ids: HashSet<&str> = comments.iter().filter_map(|comment| comment["id"].as_str()).collect();
+
+    // Comments by the fullname of their parent. Those whose parent comment
+    // is missing from the dumps are shown as replies to the post.
+    let mut children: HashMap<String, Vec<&Value>> = HashMap::new();
+    for comment in comments {
+        let parent = match comment["parent_id"].as_str().and_then(Fullname::from_string) {
+            Some(parent) if parent.kind == ThingKind::Comment && ids.contains(parent.id.as_str()) => parent,
+            _ => post.clone(),
+        };
+        children.entry(parent.to_string()).or_default().push(comment);
+    }
+
+    let listing = json!([
+        {"kind": "Listing", "data": {"children": [{"kind": "t3", "data": submission}]}},
+        {"kind": "Listing", "data": {"children": replies(&post, &mut children)}}
+    ]);
+    RedditThread::new(listing)?.thread()
+}
+
+/// Replies to a post or comment in the shape of Reddit's API, best first
+fn replies(parent: &Fullname, children: &mut HashMap<String, Vec<&Value>>) -> Vec<Value> {
+    let mut comments = children.remove(&parent.to_string()).unwrap_or_default();
+    comments.sort_by_key(|comment| (Reverse(comment["score"].as_i64().unwrap_or_default()), comment["created_utc"].as_f64().unwrap_or_default() as u64));
+
+    comments
+        .into_iter()
+        .map(|comment| {
+            let mut data = comment.clone();
+            let fullname = Fullname {
+                kind: ThingKind::Comment,
+                id: data["id"].as_str().unwrap_or_default().to_string(),
+            };
+            if data["body_html"].is_null() {
+                data["body_html"] = Value::from(format_selftext(data["body"].as_str().unwrap_or_default()));
+            }
+            // The parent of a comment whose parent is missing is the post
+            data["parent_id"] = Value::from(parent.to_string());
+            let replies = replies(&fullname, children);
+            data["replies"] = if replies.is_empty() { Value::from("") } else { json!({"kind": "Listing", "data": {"children": replies}}) };
+            json!({"kind": "t1", "data": data})
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn test_dump_threads() {
+        let lines = [
+            json!({"id": "abc123", "title": "Hello dump", "subreddit": "rust", "author": "ferris", "selftext": "**hi**", "created_utc": 1600000000}),
+            json!({"id": "c1", "link_id": "t3_abc123", "parent_id": "t3_abc123", "author": "bob", "body": "first", "score": 1, "created_utc": 1600000100}),
+            json!({"id": "c2", "link_id": "t3_abc123", "parent_id": "t1_c1", "author": "ferris", "body": "reply", "score": 1, "created_utc": 1600000200}),
+            json!({"id": "c3", "link_id": "t3_abc123", "parent_id": "t1_gone", "author": "eve", "body": "orphan", "score": 5, "created_utc": 1600000300}),
+            json!({"id": "c4", "link_id": "t3_other", "parent_id": "t3_other", "author": "eve", "body": "elsewhere", "created_utc": 1600000400}),
+        ];
+        let ndjson = |lines: &[Value]| lines.iter().map(|line| format!("{line}\n")).collect::<String>();
+
+        let dir = std::env::temp_dir().join(format!("ssg-dump-test-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let submissions = dir.join("rust_submissions.zst");
+        let comments = dir.join("rust_comments.ndjson");
+        write(&submissions, zstd::encode_all(ndjson(&lines[..1]).as_bytes(), 3).unwrap()).unwrap();
+        write(&comments, ndjson(&lines[1..]) + "\n").unwrap();
+        // Submissions of a dump that starts with a comment are skipped
+        let mixed = dir.join("mixed.ndjson");
+        write(&mixed, ndjson(&[lines[4].clone(), json!({"id": "def456", "title": "Mixed", "subreddit": "rust", "created_utc": 1600000500})])).unwrap();
+
+        let index = DumpIndex::new(&[submissions.clone(), comments.clone(), mixed.clone()]).unwrap();
+        assert_eq!(index.threads(&comments).unwrap().count(), 0);
+        assert_eq!(index.threads(&mixed).unwrap().count(), 0);
+        let threads: Vec<Thread> = index.threads(&submissions).unwrap().collect::<Result<_, _>>().unwrap();
+        remove_dir_all(&dir).unwrap();
+
+        let (post, comments) = &threads[0];
+        assert_eq!(post.title, "Hello dump");
+        assert_eq!(post.body, "<p><strong>hi</strong></p>");
+        // Best first, with the orphan under the post
+        assert_eq!(comments.iter().map(|c| c.id.as_str()).collect::<Vec<_>>(), vec!["c3", "c1"]);
+        assert_eq!(comments[0].parent_kind, "t3");
+        assert_eq!(comments[1].replies[0].id, "c2");
+        assert_eq!(comments[1].replies[0].body, "<p>reply</p>");
+
+        let gathered = index.comments().unwrap();
+        assert_eq!(gathered.threads.len(), 1);
+        assert_eq!(gathered.skipped, vec![mixed]);
+        // Only the fields the comments are built from are kept
+        assert!(gathered.threads["abc123"].iter().all(|comment| comment["link_id"].is_null() && comment["body"].is_string()));
+    }
+}
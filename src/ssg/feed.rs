@@ -0,0 +1,199 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// RSS and Atom feeds of a static archive. Feed readers need absolute links,
+// so feeds are only written when the archive is given a base URL.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use atom_syndication::{Content, Entry, Feed, FixedDateTime, Generator, Link, Person, Text};
+use rss::extension::dublincore::DublinCoreExtension;
+use rss::{ChannelBuilder, Guid, Item};
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use time::OffsetDateTime;
+
+use crate::ssg::util::{archive_url, page_url};
+use crate::utils::Post;
+
+/// Name of a feed in RSS format, relative to the page it belongs to
+pub const RSS_FILE: &str = "rss.xml";
+
+/// Name of a feed in Atom format, relative to the page it belongs to
+pub const ATOM_FILE: &str = "atom.xml";
+
+/// Number of posts in each feed
+const FEED_LENGTH: usize = 50;
+
+/// A feed of the newest posts of a page of the archive
+pub struct ArchiveFeed<'a> {
+    pub title: String,
+    pub description: String,
+    /// Absolute URL of the archive, without a trailing slash
+    pub base_url: &'a str,
+    /// Directory of the page the feed belongs to, relative to the archive root
+    pub dir: PathBuf,
+    /// Posts, newest first
+    pub posts: &'a [Post],
+}
+
+impl ArchiveFeed<'_> {
+    /// Absolute URL of a path relative to the archive root
+    fn url(&self, path: impl AsRef<Path>) -> String {
+        page_url(self.base_url, path.as_ref())
+    }
+
+    fn home_url(&self) -> String {
+        self.url(self.dir.join("index.html"))
+    }
+
+    fn posts(&self) -> &[Post] {
+        &self.posts[..self.posts.len().min(FEED_LENGTH)]
+    }
+
+    /// Both feeds, keyed by their path relative to the archive root
+    pub fn files(&self) -> Result<Vec<(PathBuf, String)>, Box<dyn Error>> {
+        Ok(vec![(self.dir.join(RSS_FILE), self.to_rss()?), (self.dir.join(ATOM_FILE), self.to_atom()?)])
+    }
+
+    pub fn to_rss(&self) -> Result<String, Box<dyn Error>> {
+        let mut items = Vec::new();
+        for post in self.posts() {
+            let link = archive_url(self.base_url, &post.permalink);
+            items.push(Item {
+                title: Some(post.title.clone()),
+                link: Some(link.clone()),
+                content: Some(post.body.clone()),
+                guid: Some(Guid { value: link, permalink: true }),
+                pub_date: Some(timestamp(post.created_ts)?.format(&Rfc2822)?),
+                // `author` is meant for an email address, so the username goes in `dc:creator`
+                dublin_core_ext: (!post.author.name.is_empty()).then(|| DublinCoreExtension {
+                    creators: vec![post.author.name.clone()],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+        }
+
+        let channel = ChannelBuilder::default()
+            .title(&self.title)
+            .link(self.home_url())
+            .description(&self.description)
+            .items(items)
+            .build();
+
+        Ok(channel.to_string())
+    }
+
+    pub fn to_atom(&self) -> Result<String, Box<dyn Error>> {
+        let mut entries = Vec::new();
+        for post in self.posts() {
+            let link = archive_url(self.base_url, &post.permalink);
+            let updated = fixed_date_time(post.created_ts)?;
+            entries.push(Entry {
+                title: Text::plain(&post.title),
+                id: link.clone(),
+                updated,
+                published: Some(updated),
+                // Left out when the author is unknown, rather than given no name
+                authors: (!post.author.name.is_empty())
+                    .then(|| Person {
+                        name: post.author.name.clone(),
+                        ..Default::default()
+                    })
+                    .into_iter()
+                    .collect(),
+                links: vec![Link {
+                    href: link,
+                    ..Default::default()
+                }],
+                content: Some(Content {
+                    value: Some(post.body.clone()),
+                    content_type: Some("html".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+        }
+
+        let feed = Feed {
+            title: Text::plain(&self.title),
+            subtitle: Some(Text::plain(&self.description)),
+            id: self.url(self.dir.join(ATOM_FILE)),
+            updated: fixed_date_time(self.posts.first().map_or(0, |post| post.created_ts))?,
+            links: vec![
+                Link {
+                    href: self.home_url(),
+                    ..Default::default()
+                },
+                Link {
+                    href: self.url(self.dir.join(ATOM_FILE)),
+                    rel: "self".to_string(),
+                    ..Default::default()
+                },
+            ],
+            generator: Some(Generator {
+                value: "Redlib".to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                ..Default::default()
+            }),
+            entries,
+            ..Default::default()
+        };
+
+        Ok(feed.to_string())
+    }
+}
+
+fn timestamp(ts: u64) -> Result<OffsetDateTime, Box<dyn Error>> {
+    Ok(OffsetDateTime::from_unix_timestamp(i64::try_from(ts)?)?)
+}
+
+fn fixed_date_time(ts: u64) -> Result<FixedDateTime, Box<dyn Error>> {
+    // chrono is built without `std`, so its errors are not `Error`s
+    Ok(timestamp(ts)?.format(&Rfc3339)?.parse().map_err(|e| format!("invalid date: {e}"))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::parse_post;
+    use futures_lite::future::block_on;
+    use serde_json::json;
+
+    #[test]
+    fn test_feeds() {
+        let mut post = block_on(parse_post(&json!({"data": {
+            "id": "abc123",
+            "title": "Hello archive",
+            "author": "ferris",
+            "created_utc": 1600000000.0
+        }})));
+        post.permalink = "abc123.html".to_string();
+        let mut unknown = post.clone();
+        unknown.author.name = String::new();
+        let posts = vec![post, unknown];
+
+        let feed = ArchiveFeed {
+            title: "r/rust".to_string(),
+            description: "1 archived post".to_string(),
+            base_url: "https://archive.example",
+            dir: PathBuf::from("r/rust"),
+            posts: &posts,
+        };
+
+        let rss = feed.to_rss().unwrap();
+        assert!(rss.contains("<link>https://archive.example/r/rust/index.html</link>"));
+        assert!(rss.contains("<guid>https://archive.example/abc123.html</guid>"));
+        assert!(rss.contains("<pubDate>Sun, 13 Sep 2020 12:26:40 +0000</pubDate>"));
+        assert_eq!(rss.matches("<dc:creator>ferris</dc:creator>").count(), 1);
+        assert!(!rss.contains("<author>") && !rss.contains("<dc:creator></dc:creator>"));
+
+        let atom = feed.to_atom().unwrap();
+        assert!(atom.contains("<id>https://archive.example/abc123.html</id>"));
+        assert!(atom.contains("<updated>2020-09-13T12:26:40+00:00</updated>"));
+        assert!(atom.contains(r#"href="https://archive.example/r/rust/atom.xml" rel="self""#));
+        assert_eq!(atom.matches("<author>").count(), 1);
+        assert!(atom.contains("<author><name>ferris</name></author>"));
+    }
+}
@@ -0,0 +1,215 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Content filters for publishing a curated part of an archive. Posts are
+// dropped by the first rule they fail, so that the build can report what
+// each rule left out.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use serde::Deserialize;
+use time::{Date, Month};
+
+use crate::ssg::search::strip_html;
+use crate::utils::{Comment, Post};
+
+/// Posts to leave out of a build, from the `[filters]` table of the site
+/// config. Names are matched case-insensitively.
+#[derive(Default, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentFilter {
+    pub min_score: Option<i64>,
+    /// First day to include, as `YYYY-MM-DD` (UTC)
+    pub since: Option<String>,
+    /// Last day to include, as `YYYY-MM-DD` (UTC)
+    pub until: Option<String>,
+    /// Authors to include. Everyone is included if empty.
+    pub authors: Vec<String>,
+    pub exclude_authors: Vec<String>,
+    /// Subreddits to include. Every subreddit is included if empty.
+    pub subreddits: Vec<String>,
+    pub exclude_subreddits: Vec<String>,
+    pub exclude_nsfw: bool,
+    /// Remove `[deleted]` and `[removed]` comments. Those with replies are
+    /// kept, so that the replies stay in their thread.
+    pub remove_deleted_comments: bool,
+}
+
+/// Rule a post was dropped by
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum FilterRule {
+    MinScore,
+    DateRange,
+    Author,
+    Subreddit,
+    Nsfw,
+}
+
+impl fmt::Display for FilterRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            FilterRule::MinScore => "below the minimum score",
+            FilterRule::DateRange => "outside of the date range",
+            FilterRule::Author => "by a filtered author",
+            FilterRule::Subreddit => "in a filtered subreddit",
+            FilterRule::Nsfw => "NSFW",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl ContentFilter {
+    /// Check that the dates can be parsed.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        self.range()?;
+        Ok(())
+    }
+
+    /// Unix timestamps of the start of `since` and the end of `until`
+    fn range(&self) -> Result<(Option<u64>, Option<u64>), Box<dyn Error>> {
+        let start = |date: &Option<String>| date.as_deref().map(parse_date).transpose();
+        Ok((start(&self.since)?, start(&self.until)?.map(|until| until + 24 * 60 * 60)))
+    }
+
+    /// The rule a post fails, if any
+    pub fn check(&self, post: &Post) -> Option<FilterRule> {
+        let matches = |names: &[String], name: &str| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+
+        if self.min_score.is_some_and(|min| post.score.1.parse::<i64>().unwrap_or_default() < min) {
+            return Some(FilterRule::MinScore);
+        }
+        let (since, until) = self.range().unwrap_or_default();
+        if since.is_some_and(|since| post.created_ts < since) || until.is_some_and(|until| post.created_ts >= until) {
+            return Some(FilterRule::DateRange);
+        }
+        if (!self.authors.is_empty() && !matches(&self.authors, &post.author.name)) || matches(&self.exclude_authors, &post.author.name) {
+            return Some(FilterRule::Author);
+        }
+        if (!self.subreddits.is_empty() && !matches(&self.subreddits, &post.community)) || matches(&self.exclude_subreddits, &post.community) {
+            return Some(FilterRule::Subreddit);
+        }
+        if self.exclude_nsfw && post.flags.nsfw {
+            return Some(FilterRule::Nsfw);
+        }
+        None
+    }
+
+    /// Remove deleted comments from a comment tree, if asked to. Returns the
+    /// number of comments removed.
+    pub fn filter_comments(&self, comments: &mut Vec<Comment>) -> usize {
+        if !self.remove_deleted_comments {
+            return 0;
+        }
+
+        let mut removed = 0;
+        comments.retain_mut(|comment| {
+            removed += self.filter_comments(&mut comment.replies);
+            let keep = !(comment.replies.is_empty() && is_deleted(comment));
+            if !keep {
+                removed += 1;
+            }
+            keep
+        });
+        removed
+    }
+}
+
+fn is_deleted(comment: &Comment) -> bool {
+    comment.kind == "t1" && matches!(strip_html(&comment.body).trim(), "[deleted]" | "[removed]")
+}
+
+fn parse_date(date: &str) -> Result<u64, Box<dyn Error>> {
+    let invalid = || format!("invalid date {date:?}, expected YYYY-MM-DD");
+    let parts: Vec<&str> = date.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return Err(invalid().into());
+    };
+    let date = Date::from_calendar_date(year.parse()?, Month::try_from(month.parse::<u8>()?)?, day.parse()?).map_err(|_| invalid())?;
+    Ok(u64::try_from(date.midnight().assume_utc().unix_timestamp())?)
+}
+
+/// Number of posts dropped by each rule, and of comments removed
+#[derive(Default)]
+pub struct FilterStats {
+    pub dropped: BTreeMap<FilterRule, usize>,
+    pub removed_comments: usize,
+}
+
+impl FilterStats {
+    /// Lines of the build summary, empty if nothing was filtered
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .dropped
+            .iter()
+            .map(|(rule, count)| format!("Dropped {count} {} {rule}", if *count == 1 { "post" } else { "posts" }))
+            .collect();
+        if self.removed_comments > 0 {
+            lines.push(format!("Removed {} deleted {}", self.removed_comments, if self.removed_comments == 1 { "comment" } else { "comments" }));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::parse_post;
+    use futures_lite::future::block_on;
+    use serde_json::json;
+
+    #[test]
+    fn test_check() {
+        let post = block_on(parse_post(&json!({"data": {
+            "id": "abc123",
+            "subreddit": "rust",
+            "author": "ferris",
+            "score": 10,
+            "over_18": true,
+            "created_utc": 1600000000.0
+        }})));
+
+        assert_eq!(ContentFilter::default().check(&post), None);
+        let filter = |toml: &str| toml::from_str::<ContentFilter>(toml).unwrap().check(&post);
+        assert_eq!(filter("min_score = 11"), Some(FilterRule::MinScore));
+        assert_eq!(filter("since = \"2020-09-13\"\nuntil = \"2020-09-13\""), None);
+        assert_eq!(filter("since = \"2020-09-14\""), Some(FilterRule::DateRange));
+        assert_eq!(filter("until = \"2020-09-12\""), Some(FilterRule::DateRange));
+        assert_eq!(filter("authors = [\"Ferris\"]"), None);
+        assert_eq!(filter("exclude_authors = [\"ferris\"]"), Some(FilterRule::Author));
+        assert_eq!(filter("subreddits = [\"golang\"]"), Some(FilterRule::Subreddit));
+        assert_eq!(filter("exclude_nsfw = true"), Some(FilterRule::Nsfw));
+        assert!(toml::from_str::<ContentFilter>("since = \"13/09/2020\"").unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn test_filter_comments() {
+        let comment = |id: &str, body: &str, replies: serde_json::Value| json!({"kind": "t1", "data": {
+            "id": id,
+            "body_html": body,
+            "parent_id": "t3_abc123",
+            "replies": replies
+        }});
+        let (_, mut comments) = crate::ssg::template::InputFormat::RedditJson
+            .json_decode(json!([
+                {"data": {"children": [{"kind": "t3", "data": {"id": "abc123"}}]}},
+                {"data": {"children": [
+                    comment("a", "<p>[deleted]</p>", json!("")),
+                    comment("b", "<p>[removed]</p>", json!({"data": {"children": [comment("c", "<p>hi</p>", json!(""))]}})),
+                    comment("d", "<p>[deleted] by mistake</p>", json!("")),
+                ]}}
+            ]))
+            .unwrap()
+            .thread()
+            .unwrap();
+
+        assert_eq!(ContentFilter::default().filter_comments(&mut comments), 0);
+        let filter = ContentFilter {
+            remove_deleted_comments: true,
+            ..ContentFilter::default()
+        };
+        assert_eq!(filter.filter_comments(&mut comments), 1);
+        assert_eq!(comments.iter().map(|c| c.id.as_str()).collect::<Vec<_>>(), vec!["b", "d"]);
+    }
+}
@@ -0,0 +1,405 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Reddit's data export, as sent in answer to a GDPR request: a directory of
+// CSV files like `posts.csv` and `comments.csv` holding what an account
+// posted. It is archived as the account's posts, each with the account's
+// comments on it, and placeholder threads for its other comments that link to
+// the threads on Reddit. The export has no scores, nor anyone else's content.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use time::macros::format_description;
+use time::PrimitiveDateTime;
+use url::Url;
+
+use crate::models::{Fullname, ThingKind};
+use crate::ssg::manifest::hash_file;
+use crate::ssg::template::Thread;
+use crate::utils::{format_num, format_selftext, time, Author, Comment, Flags, Flair, Media, Post, Preferences};
+
+/// Posts of the account
+pub const POSTS_FILE: &str = "posts.csv";
+
+/// Comments of the account
+pub const COMMENTS_FILE: &str = "comments.csv";
+
+/// Details of the account, like its name
+pub const STATISTICS_FILE: &str = "statistics.csv";
+
+/// Files of an export that are archived
+const EXPORT_FILES: [&str; 3] = [STATISTICS_FILE, POSTS_FILE, COMMENTS_FILE];
+
+/// A row of `posts.csv`
+#[derive(Clone, Deserialize)]
+pub struct ExportPost {
+    pub id: String,
+    /// URL of the post on Reddit
+    pub permalink: String,
+    /// Date the post was created, like "2023-05-01 12:00:00 UTC"
+    pub date: String,
+    /// Subreddit name
+    pub subreddit: String,
+    #[serde(default)]
+    pub title: String,
+    /// Link of the post, or its permalink for text posts
+    #[serde(default)]
+    pub url: String,
+    /// Markdown of the post's text
+    #[serde(default)]
+    pub body: String,
+}
+
+/// A row of `comments.csv`
+#[derive(Clone, Deserialize)]
+pub struct ExportComment {
+    pub id: String,
+    /// URL of the comment on Reddit
+    pub permalink: String,
+    /// Date the comment was created, like "2023-05-01 12:00:00 UTC"
+    pub date: String,
+    /// Subreddit name
+    pub subreddit: String,
+    /// URL of the thread on Reddit
+    pub link: String,
+    /// ID or fullname of the post or comment replied to
+    #[serde(default)]
+    pub parent: String,
+    /// Markdown of the comment
+    #[serde(default)]
+    pub body: String,
+}
+
+/// Unix timestamp of a date of the export
+fn timestamp(date: &str) -> Result<u64, Box<dyn Error>> {
+    let date = PrimitiveDateTime::parse(date.trim().trim_end_matches(" UTC"), format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"))?;
+    Ok(date.assume_utc().unix_timestamp().max(0) as u64)
+}
+
+/// Path of a Reddit URL, like `/r/rust/comments/abc123/title/`
+fn url_path(url: &str) -> String {
+    Url::parse(url).map_or_else(|_| url.to_string(), |url| url.path().to_string())
+}
+
+/// ID and title slug of the thread a Reddit URL points to
+fn thread_of(url: &str) -> Option<(String, String)> {
+    let path = url_path(url);
+    let mut segments = path.split('/').skip_while(|segment| *segment != "comments").skip(1);
+    let id = segments.next().filter(|id| !id.is_empty())?;
+    Some((id.to_string(), segments.next().unwrap_or_default().to_string()))
+}
+
+impl ExportPost {
+    /// Convert to a Post of `author`, for PostTemplate
+    pub fn to_post(&self, author: &str, num_comments: usize) -> Result<Post, Box<dyn Error>> {
+        let created_ts = timestamp(&self.date)?;
+        let (rel_time, created) = time(created_ts as f64);
+        let permalink = url_path(&self.permalink);
+        let is_self = self.url.is_empty() || url_path(&self.url) == permalink;
+        let media = if is_self {
+            Media::default()
+        } else {
+            Media {
+                url: self.url.clone(),
+                alt_url: self.url.clone(),
+                ..Media::default()
+            }
+        };
+        let domain = if is_self {
+            format!("self.{}", self.subreddit)
+        } else {
+            Url::parse(&self.url).ok().and_then(|url| url.host_str().map(str::to_string)).unwrap_or_default()
+        };
+
+        Ok(Post {
+            id: self.id.clone(),
+            title: self.title.clone(),
+            community: self.subreddit.clone(),
+            body: format_selftext(&self.body),
+            author: Author {
+                name: author.to_string(),
+                flair: Flair::default(),
+                distinguished: String::new(),
+            },
+            permalink,
+            link_title: String::new(),
+            poll: None,
+            score: format_num(0),
+            upvote_ratio: 0,
+            post_type: if is_self { "self" } else { "link" }.to_string(),
+            flair: Flair::default(),
+            flags: Flags {
+                spoiler: false,
+                nsfw: false,
+                stickied: false,
+                locked: false,
+            },
+            thumbnail: Media::default(),
+            media,
+            domain,
+            rel_time,
+            created,
+            created_ts,
+            num_duplicates: 0,
+            comments: format_num(num_comments as i64),
+            gallery: Vec::new(),
+            awards: Vec::new(),
+            nsfw: false,
+            out_url: None,
+            ws_url: String::new(),
+        })
+    }
+}
+
+impl ExportComment {
+    /// Fullname of the post or comment replied to. Bare IDs are those of a
+    /// comment, unless they are the thread's.
+    fn parent_fullname(&self, post_id: &str) -> Fullname {
+        Fullname::from_string(&self.parent).unwrap_or_else(|| Fullname {
+            kind: if self.parent.is_empty() || self.parent == post_id { ThingKind::Link } else { ThingKind::Comment },
+            id: if self.parent.is_empty() { post_id.to_string() } else { self.parent.clone() },
+        })
+    }
+
+    /// Convert to a Comment of `author` on `post`, for PostTemplate
+    pub fn to_comment(&self, post: &Post, author: &str, parent: &Fullname) -> Result<Comment, Box<dyn Error>> {
+        let created_ts = timestamp(&self.date)?;
+        let (rel_time, created) = time(created_ts as f64);
+
+        Ok(Comment {
+            id: self.id.clone(),
+            kind: ThingKind::Comment.to_string(),
+            parent_id: parent.to_string(),
+            parent_kind: parent.kind.to_string(),
+            post_link: post.permalink.clone(),
+            post_author: post.author.name.clone(),
+            body: format_selftext(&self.body),
+            author: Author {
+                name: author.to_string(),
+                flair: Flair::default(),
+                distinguished: String::new(),
+            },
+            score: format_num(0),
+            rel_time,
+            created,
+            created_ts,
+            edited: (String::new(), String::new()),
+            replies: Vec::new(),
+            highlighted: false,
+            awards: Vec::new(),
+            collapsed: false,
+            is_filtered: false,
+            more_count: 0,
+            prefs: Preferences::default(),
+        })
+    }
+}
+
+/// The files of an export that are archived
+pub struct Export {
+    /// Name of the account, empty if the export does not have it
+    pub author: String,
+    pub posts: Vec<ExportPost>,
+    pub comments: Vec<ExportComment>,
+}
+
+impl Export {
+    /// Read the export in a directory. Missing files are taken to be empty.
+    pub fn read(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let rows = |name: &str| -> Result<Option<csv::Reader<std::fs::File>>, Box<dyn Error>> {
+            let path = dir.join(name);
+            Ok(if path.exists() { Some(csv::Reader::from_path(path)?) } else { None })
+        };
+
+        let mut author = String::new();
+        if let Some(mut statistics) = rows(STATISTICS_FILE)? {
+            for record in statistics.records() {
+                let record = record?;
+                if record.get(0) == Some("account name") {
+                    author = record.get(1).unwrap_or_default().to_string();
+                }
+            }
+        }
+        if author.is_empty() {
+            warn!("{} has no account name, the export's posts will not have an author", dir.join(STATISTICS_FILE).display());
+        }
+
+        Ok(Self {
+            author,
+            posts: rows(POSTS_FILE)?.map_or(Ok(Vec::new()), |mut posts| posts.deserialize().collect())?,
+            comments: rows(COMMENTS_FILE)?.map_or(Ok(Vec::new()), |mut comments| comments.deserialize().collect())?,
+        })
+    }
+
+    /// The account's posts, with its comments on them
+    pub fn posts(&self) -> Result<Vec<Thread>, Box<dyn Error>> {
+        let mut comments = self.comments_by_thread();
+        self.posts
+            .iter()
+            .map(|post| {
+                let comments = comments.remove(&post.id).unwrap_or_default();
+                let post = post.to_post(&self.author, comments.len())?;
+                let comments = self.comment_tree(&post, &comments)?;
+                Ok((post, comments))
+            })
+            .collect()
+    }
+
+    /// Placeholders for the threads of the account's comments on other posts,
+    /// which link to the threads on Reddit
+    pub fn comment_threads(&self) -> Result<Vec<Thread>, Box<dyn Error>> {
+        let posts: HashSet<&str> = self.posts.iter().map(|post| post.id.as_str()).collect();
+        let mut threads = Vec::new();
+        for (id, comments) in self.comments_by_thread() {
+            if posts.contains(id.as_str()) {
+                continue;
+            }
+            // The thread's date is unknown, it is that of the first comment
+            let first = comments[0];
+            let (_, slug) = thread_of(&first.link).unwrap_or_default();
+            let placeholder = ExportPost {
+                id,
+                permalink: String::new(),
+                date: first.date.clone(),
+                subreddit: first.subreddit.clone(),
+                title: if slug.is_empty() { first.link.clone() } else { slug.replace('_', " ") },
+                url: first.link.clone(),
+                body: String::new(),
+            };
+            let post = placeholder.to_post("", comments.len())?;
+            let comments = self.comment_tree(&post, &comments)?;
+            threads.push((post, comments));
+        }
+        Ok(threads)
+    }
+
+    /// The account's comments, by the ID of their thread, oldest first
+    fn comments_by_thread(&self) -> BTreeMap<String, Vec<&ExportComment>> {
+        let mut threads: BTreeMap<String, Vec<&ExportComment>> = BTreeMap::new();
+        for comment in &self.comments {
+            match thread_of(&comment.link).or_else(|| thread_of(&comment.permalink)) {
+                Some((id, _)) => threads.entry(id).or_default().push(comment),
+                None => warn!("Comment {} of the export has no thread, leaving it out", comment.id),
+            }
+        }
+        for comments in threads.values_mut() {
+            comments.sort_by_key(|comment| timestamp(&comment.date).unwrap_or_default());
+        }
+        threads
+    }
+
+    /// Comment tree of a thread. Comments replying to one that is not in the
+    /// export are shown as replies to the post.
+    fn comment_tree(&self, post: &Post, comments: &[&ExportComment]) -> Result<Vec<Comment>, Box<dyn Error>> {
+        fn replies(parent: &str, children: &mut HashMap<String, Vec<Comment>>) -> Vec<Comment> {
+            let mut comments = children.remove(parent).unwrap_or_default();
+            for comment in &mut comments {
+                comment.replies = replies(&Fullname { kind: ThingKind::Comment, id: comment.id.clone() }.to_string(), children);
+            }
+            comments
+        }
+
+        let ids: HashSet<&str> = comments.iter().map(|comment| comment.id.as_str()).collect();
+        let post_fullname = Fullname { kind: ThingKind::Link, id: post.id.clone() };
+        let mut children: HashMap<String, Vec<Comment>> = HashMap::new();
+        for comment in comments {
+            let parent = match comment.parent_fullname(&post.id) {
+                parent if parent.kind == ThingKind::Comment && ids.contains(parent.id.as_str()) => parent,
+                _ => post_fullname.clone(),
+            };
+            children.entry(parent.to_string()).or_default().push(comment.to_comment(post, &self.author, &parent)?);
+        }
+        Ok(replies(&post_fullname.to_string(), &mut children))
+    }
+}
+
+/// Hash of the archived files of the export an input file belongs to: its
+/// posts and comments are built from all of them.
+pub fn export_hash(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+    for file in export_files(path) {
+        if file.exists() {
+            hasher.update(hash_file(&file)?);
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn export_files(path: &Path) -> Vec<PathBuf> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    EXPORT_FILES.iter().map(|name| dir.join(name)).collect()
+}
+
+/// Threads of a file of an export: the account's posts for `posts.csv`, the
+/// threads of its other comments for `comments.csv`, and none for the rest.
+pub fn threads(path: &Path) -> Result<Vec<Thread>, Box<dyn Error>> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(POSTS_FILE) => Export::read(dir)?.posts(),
+        Some(COMMENTS_FILE) => Export::read(dir)?.comment_threads(),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn test_export_threads() {
+        let dir = std::env::temp_dir().join(format!("ssg-gdpr-test-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        write(dir.join(STATISTICS_FILE), "statistic,value\naccount name,ferris\nexport time,2024-01-01 00:00:00 UTC\n").unwrap();
+        write(
+            dir.join(POSTS_FILE),
+            "id,permalink,date,ip,subreddit,gildings,title,url,body\n\
+             abc123,https://www.reddit.com/r/rust/comments/abc123/hello/,2023-05-01 12:00:00 UTC,,rust,0,Hello,https://www.reddit.com/r/rust/comments/abc123/hello/,\"**hi**, all\"\n\
+             def456,https://www.reddit.com/r/rust/comments/def456/a_link/,2023-05-02 12:00:00 UTC,,rust,0,A link,https://example.com/x,\n",
+        )
+        .unwrap();
+        write(
+            dir.join(COMMENTS_FILE),
+            "id,permalink,date,ip,subreddit,gildings,link,parent,body,media\n\
+             c1,https://www.reddit.com/r/rust/comments/abc123/hello/c1/,2023-05-01 13:00:00 UTC,,rust,0,https://www.reddit.com/r/rust/comments/abc123/hello/,t1_zz,first,\n\
+             c2,https://www.reddit.com/r/rust/comments/abc123/hello/c2/,2023-05-01 14:00:00 UTC,,rust,0,https://www.reddit.com/r/rust/comments/abc123/hello/,c1,second,\n\
+             c3,https://www.reddit.com/r/golang/comments/xyz789/why_go/c3/,2023-05-03 12:00:00 UTC,,golang,0,https://www.reddit.com/r/golang/comments/xyz789/why_go/,xyz789,elsewhere,\n",
+        )
+        .unwrap();
+
+        let posts = threads(&dir.join(POSTS_FILE)).unwrap();
+        let others = threads(&dir.join(COMMENTS_FILE)).unwrap();
+        assert!(threads(&dir.join("saved_posts.csv")).unwrap().is_empty());
+        let hash = export_hash(&dir.join(POSTS_FILE)).unwrap();
+        assert_eq!(hash, export_hash(&dir.join(COMMENTS_FILE)).unwrap());
+        remove_dir_all(&dir).unwrap();
+
+        let (post, comments) = &posts[0];
+        assert_eq!(post.author.name, "ferris");
+        assert_eq!(post.permalink, "/r/rust/comments/abc123/hello/");
+        assert_eq!(post.post_type, "self");
+        assert_eq!(post.body, "<p><strong>hi</strong>, all</p>");
+        assert_eq!(post.created_ts, 1682942400);
+        assert_eq!(post.comments.0, "2");
+        // The reply to a comment missing from the export is a top-level one
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].parent_id, "t3_abc123");
+        assert_eq!(comments[0].replies[0].id, "c2");
+        assert_eq!(comments[0].replies[0].parent_id, "t1_c1");
+        assert_eq!(posts[1].0.post_type, "link");
+        assert_eq!(posts[1].0.domain, "example.com");
+
+        assert_eq!(others.len(), 1);
+        let (placeholder, comments) = &others[0];
+        assert_eq!((placeholder.id.as_str(), placeholder.title.as_str(), placeholder.community.as_str()), ("xyz789", "why go", "golang"));
+        assert_eq!(placeholder.media.url, "https://www.reddit.com/r/golang/comments/xyz789/why_go/");
+        assert!(placeholder.author.name.is_empty());
+        assert_eq!(comments[0].author.name, "ferris");
+        assert_eq!(comments[0].parent_kind, "t3");
+    }
+}
@@ -0,0 +1,291 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Links between the posts of a static archive. Post bodies and comments link
+// to Reddit threads, which are often archived too: those links are pointed at
+// the archived pages, and the rest at Reddit, since the live routes they are
+// rewritten to by `utils::rewrite_urls` do not exist in a static site. For
+// the same reason, media are pointed back at Reddit's media hosts.
+
+use std::collections::{BTreeMap, HashMap};
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use crate::ssg::manifest::ManifestEntry;
+use crate::ssg::util::{comment_page_href, page_url};
+use crate::utils::{Comment, Post};
+
+static LINK_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)<a\s[^>]*>"#).unwrap());
+static MEDIA_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)<(?:img|video|source)\s[^>]*>"#).unwrap());
+static HREF: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)\bhref="([^"]*)""#).unwrap());
+static SRC: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)\bsrc="([^"]*)""#).unwrap());
+static CLASS: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)\bclass="([^"]*)""#).unwrap());
+static REDDIT_URL: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)^(?:https?:)?//(?:(?:www|old|np|amp|new|m)\.)?(reddit\.com|redd\.it)(/[^?#]*)?").unwrap());
+static EXTERNAL_URL: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)^(?:https?:)?//").unwrap());
+static SHORT_ID: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[0-9a-z]{1,13}$").unwrap());
+
+/// Top directories of Reddit's own pages. Other site-relative links in
+/// archived content go to Redlib's media routes.
+const REDDIT_SECTIONS: [&str; 6] = ["r", "u", "user", "comments", "wiki", "message"];
+
+/// Redlib's media routes and the hosts they proxy, see `utils::format_url()`.
+/// Videos are handled by `reddit_media_url()`.
+const MEDIA_ROUTES: [(&str, &str); 10] = [
+    ("/img/", "https://i.redd.it/"),
+    ("/preview/pre/", "https://preview.redd.it/"),
+    ("/preview/external-pre/", "https://external-preview.redd.it/"),
+    ("/thumb/a/", "https://a.thumbs.redditmedia.com/"),
+    ("/thumb/b/", "https://b.thumbs.redditmedia.com/"),
+    ("/emoji/", "https://emoji.redditmedia.com/"),
+    ("/emote/", "https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/asset-manager/"),
+    ("/hls/", "https://v.redd.it/"),
+    ("/style/", "https://styles.redditmedia.com/"),
+    ("/static/", "https://www.redditstatic.com/"),
+];
+
+/// Reddit's URL of a file a Redlib media route like `/img/abc.png` proxies
+pub fn reddit_media_url(href: &str) -> Option<String> {
+    if let Some((id, size)) = href.strip_prefix("/vid/").and_then(|path| path.split_once('/')) {
+        return Some(format!("https://v.redd.it/{id}/DASH_{size}"));
+    }
+    MEDIA_ROUTES
+        .iter()
+        .find_map(|(route, host)| Some(format!("{host}{}", href.strip_prefix(route)?)))
+}
+
+/// Pages of the archived posts and comments, by Reddit ID
+#[derive(Default)]
+pub struct LinkIndex {
+    posts: HashMap<String, String>,
+    comments: HashMap<String, String>,
+}
+
+impl LinkIndex {
+    /// Index the pages of a build. If a post was archived more than once, the
+    /// first of its pages is linked to.
+    pub fn build<'a>(entries: impl IntoIterator<Item = &'a ManifestEntry>) -> Self {
+        let mut index = Self::default();
+        for entry in entries {
+            let id = entry.post.id.to_lowercase();
+            let page = page_url("", &entry.output);
+            index.posts.entry(id).or_insert(page);
+            for comment in &entry.comments {
+                index
+                    .comments
+                    .entry(comment.id.to_lowercase())
+                    .or_insert_with(|| comment_page_href(&entry.output, &comment.id));
+            }
+        }
+        index
+    }
+
+    /// Archived page of a thread, relative to the archive root. Links to
+    /// comments that were not archived go to their post, if it was.
+    fn page(&self, thread: &Thread) -> Option<String> {
+        thread
+            .comment
+            .as_ref()
+            .and_then(|comment| Some(format!("{}#{comment}", self.comments.get(comment)?)))
+            .or_else(|| self.posts.get(&thread.post).cloned())
+    }
+
+    /// Whether the links of an earlier build still point where they would now,
+    /// see `ManifestEntry::links`.
+    pub fn is_current(&self, links: &BTreeMap<String, String>) -> bool {
+        links.iter().all(|(key, page)| self.page(&Thread::from_key(key)).unwrap_or_default() == *page)
+    }
+}
+
+/// A Reddit thread, or a comment in it
+struct Thread {
+    post: String,
+    comment: Option<String>,
+}
+
+impl Thread {
+    /// Thread a Reddit URL path points to, like `/r/rust/comments/abc123/title/c1`,
+    /// or with `short`, a short link like `/abc123`
+    fn parse(path: &str, short: bool) -> Option<Self> {
+        let segments: Vec<String> = path.split('/').filter(|s| !s.is_empty()).map(str::to_lowercase).collect();
+        if let [id] = segments.as_slice() {
+            return (short && SHORT_ID.is_match(id)).then(|| Self { post: id.clone(), comment: None });
+        }
+        let i = segments.iter().position(|s| s == "comments")?;
+        Some(Self {
+            post: segments.get(i + 1).filter(|id| SHORT_ID.is_match(id))?.clone(),
+            comment: segments.get(i + 3).filter(|id| SHORT_ID.is_match(id)).cloned(),
+        })
+    }
+
+    /// Key of `ManifestEntry::links`: the post ID, followed by the comment ID
+    fn key(&self) -> String {
+        match &self.comment {
+            Some(comment) => format!("{}/{comment}", self.post),
+            None => self.post.clone(),
+        }
+    }
+
+    fn from_key(key: &str) -> Self {
+        let (post, comment) = key.split_once('/').map_or((key, None), |(post, comment)| (post, Some(comment.to_string())));
+        Self { post: post.to_string(), comment }
+    }
+}
+
+/// Rewrites the links of archived content
+pub struct LinkRewriter<'a> {
+    pub index: &'a LinkIndex,
+    /// Add the `external` class to links that leave the archive
+    pub mark_external: bool,
+}
+
+impl LinkRewriter<'_> {
+    /// Rewrite the links of an HTML fragment. The threads it links to are
+    /// added to `links`, with the archived page each link now points to.
+    pub fn rewrite(&self, html: &str, links: &mut BTreeMap<String, String>) -> String {
+        let html = MEDIA_TAG.replace_all(html, |caps: &Captures| {
+            SRC.replace(&caps[0], |src: &Captures| format!("src=\"{}\"", reddit_media_url(&src[1]).unwrap_or_else(|| src[1].to_string()))).to_string()
+        });
+        LINK_TAG
+            .replace_all(&html, |caps: &Captures| {
+                let tag = &caps[0];
+                let Some(href) = HREF.captures(tag).map(|href| href[1].to_string()) else {
+                    return tag.to_string();
+                };
+                let (href, external) = self.rewrite_href(&href, links);
+                let tag = HREF.replace(tag, |_: &Captures| format!("href=\"{href}\"")).to_string();
+                if !(external && self.mark_external) {
+                    tag
+                } else if CLASS.is_match(&tag) {
+                    CLASS.replace(&tag, |class: &Captures| format!("class=\"{} external\"", &class[1])).to_string()
+                } else {
+                    tag.replacen("<a", "<a class=\"external\"", 1)
+                }
+            })
+            .to_string()
+    }
+
+    /// Rewrite the links of a post: its body, its media and the URL of a link
+    /// post, which points at another thread for crossposts.
+    pub fn rewrite_post(&self, post: &mut Post, links: &mut BTreeMap<String, String>) {
+        post.body = self.rewrite(&post.body, links);
+        if post.post_type == "link" {
+            post.media.url = self.rewrite_href(&post.media.url, links).0;
+        }
+        let media_urls = [&mut post.media.url, &mut post.media.alt_url, &mut post.media.poster, &mut post.thumbnail.url];
+        for url in media_urls.into_iter().chain(post.gallery.iter_mut().map(|media| &mut media.url)) {
+            if let Some(reddit) = reddit_media_url(url) {
+                *url = reddit;
+            }
+        }
+    }
+
+    /// Rewrite the bodies of a comment tree
+    pub fn rewrite_comments(&self, comments: &mut [Comment], links: &mut BTreeMap<String, String>) {
+        for comment in comments {
+            comment.body = self.rewrite(&comment.body, links);
+            self.rewrite_comments(&mut comment.replies, links);
+        }
+    }
+
+    /// The new target of a link, and whether it leaves the archive
+    fn rewrite_href(&self, href: &str, links: &mut BTreeMap<String, String>) -> (String, bool) {
+        let site_relative = href.starts_with('/') && !href.starts_with("//");
+        let (path, short) = if site_relative {
+            (href.split(['?', '#']).next().unwrap_or_default().to_string(), false)
+        } else if let Some(caps) = REDDIT_URL.captures(href) {
+            (caps.get(2).map_or("", |path| path.as_str()).to_string(), caps[1].eq_ignore_ascii_case("redd.it"))
+        } else {
+            return (href.to_string(), EXTERNAL_URL.is_match(href));
+        };
+
+        // Redd.it links are rewritten to site-relative short links
+        let thread = Thread::parse(&path, short || site_relative);
+        if let Some(thread) = &thread {
+            let page = self.index.page(thread);
+            links.insert(thread.key(), page.clone().unwrap_or_default());
+            if let Some(page) = page {
+                // Keep the fragment of links to posts
+                let fragment = if thread.comment.is_none() { href.find('#').map_or("", |i| &href[i..]) } else { "" };
+                return (format!("{page}{fragment}"), false);
+            }
+        }
+
+        let section = path.split('/').find(|s| !s.is_empty()).unwrap_or_default().to_lowercase();
+        if !site_relative {
+            (href.to_string(), true)
+        } else if thread.is_some_and(|thread| thread.post == section) {
+            (format!("https://redd.it{href}"), true)
+        } else if REDDIT_SECTIONS.contains(&section.as_str()) {
+            (format!("https://www.reddit.com{href}"), true)
+        } else if let Some(url) = reddit_media_url(href) {
+            (url, true)
+        } else {
+            (href.to_string(), false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::parse_post;
+    use futures_lite::future::block_on;
+    use serde_json::json;
+
+    #[test]
+    fn test_rewrite() {
+        let index = LinkIndex {
+            posts: HashMap::from([("abc123".to_string(), "rust/hello.html".to_string())]),
+            comments: HashMap::from([("c1".to_string(), "comments/abc123/c1.html".to_string())]),
+        };
+        let rewriter = LinkRewriter { index: &index, mark_external: false };
+        let rewrite = |html: &str| {
+            let mut links = BTreeMap::new();
+            (rewriter.rewrite(html, &mut links), links)
+        };
+
+        let (html, links) = rewrite(r#"<a href="/r/rust/comments/abc123/hello/">see</a> and <a href="https://old.reddit.com/r/rust/comments/ABC123/hello/c1/?context=3">this</a>"#);
+        assert_eq!(html, r#"<a href="rust/hello.html">see</a> and <a href="comments/abc123/c1.html#c1">this</a>"#);
+        assert_eq!(links, BTreeMap::from([("abc123".to_string(), "rust/hello.html".to_string()), ("abc123/c1".to_string(), "comments/abc123/c1.html#c1".to_string())]));
+
+        // Comments that were not archived are linked to their post
+        assert_eq!(rewrite(r#"<a href="/r/rust/comments/abc123/hello/c2/">"#).0, r#"<a href="rust/hello.html">"#);
+        assert_eq!(rewrite(r#"<a href="https://redd.it/abc123#top">"#).0, r#"<a href="rust/hello.html#top">"#);
+
+        // Everything else goes to Reddit
+        let (html, links) = rewrite(r#"<a href="/r/golang/comments/def456/">"#);
+        assert_eq!(html, r#"<a href="https://www.reddit.com/r/golang/comments/def456/">"#);
+        assert_eq!(links["def456"], "");
+        assert_eq!(rewrite(r#"<a href="/r/golang">"#).0, r#"<a href="https://www.reddit.com/r/golang">"#);
+        assert_eq!(rewrite(r#"<a href="/def456">"#).0, r#"<a href="https://redd.it/def456">"#);
+        assert_eq!(rewrite(r#"<a href="/settings/profile">"#).0, r#"<a href="/settings/profile">"#);
+
+        // Redlib's media routes go back to Reddit's media hosts
+        assert_eq!(rewrite(r#"<a href="/img/x.png">"#).0, r#"<a href="https://i.redd.it/x.png">"#);
+        assert_eq!(
+            rewrite(r#"<a href="/preview/pre/x.png?width=640&s=1"><img loading="lazy" src="/preview/pre/x.png?width=640&s=1"></a>"#).0,
+            r#"<a href="https://preview.redd.it/x.png?width=640&s=1"><img loading="lazy" src="https://preview.redd.it/x.png?width=640&s=1"></a>"#
+        );
+        assert_eq!(reddit_media_url("/vid/abc/720.mp4").unwrap(), "https://v.redd.it/abc/DASH_720.mp4");
+
+        // So do crossposts and the media of posts
+        let mut post = block_on(parse_post(&json!({"data": {"id": "xyz789", "url": "https://www.reddit.com/r/rust/comments/abc123/hello/", "thumbnail": "https://b.thumbs.redditmedia.com/t.jpg"}})));
+        let mut links = BTreeMap::new();
+        rewriter.rewrite_post(&mut post, &mut links);
+        assert_eq!(post.post_type, "link");
+        assert_eq!(post.media.url, "rust/hello.html");
+        assert_eq!(post.thumbnail.url, "https://b.thumbs.redditmedia.com/t.jpg");
+        assert_eq!(links["abc123"], "rust/hello.html");
+
+        let rewriter = LinkRewriter { index: &index, mark_external: true };
+        let mut links = BTreeMap::new();
+        assert_eq!(
+            rewriter.rewrite(r#"<a href="https://example.com">a</a> <a class="x" href="/u/ferris">b</a> <a href="/abc123">c</a>"#, &mut links),
+            r#"<a class="external" href="https://example.com">a</a> <a class="x external" href="https://www.reddit.com/u/ferris">b</a> <a href="rust/hello.html">c</a>"#
+        );
+
+        assert!(index.is_current(&links));
+        assert!(!index.is_current(&BTreeMap::from([("abc123".to_string(), String::new())])));
+    }
+}
@@ -1,25 +1,55 @@
 #![forbid(unsafe_code)]
 #![allow(clippy::cmp_owned)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::error::Error;
-use std::fs::{canonicalize, create_dir_all, read_dir, File};
+use std::fs::{canonicalize, create_dir_all, read_dir, remove_dir, remove_dir_all, remove_file, File};
+use std::io::BufReader;
+use std::iter::once;
 use std::path::{Path, PathBuf};
-
-use clap::Parser;
+use std::process;
+use std::slice;
+use std::thread;
+use std::time::Duration;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use log::{debug, warn};
 use serde_json::Value;
 
-use redlib::bdfr::SubmissionArchiveEntry;
 use redlib::ssg::{
-    template::{InputFormat, create_subreddit},
-    util::output_path,
-    writer::write_all,
+    api::{api_index, api_post, api_post_path, api_user_path, api_users, API_INDEX_FILE},
+    browse::{create_date_pages, create_flair_pages},
+    config::SiteConfig,
+    dump::DumpIndex,
+    manifest::{hash_file, Manifest, ManifestEntry},
+    media::{fingerprint, localize, MediaIndex},
+    preview::{serve, watch},
+    feed::{ArchiveFeed, ATOM_FILE, RSS_FILE},
+    filter::{ContentFilter, FilterRule, FilterStats},
+    gdpr::{self, export_hash},
+    links::{LinkIndex, LinkRewriter},
+    standalone::{StandaloneComments, StandaloneInput},
+    search::{SearchEntry, SearchIndex, SearchTemplate, SEARCH_PAGE_FILE},
+    sitemap::{robots_txt, robots_txt_found, sitemaps, ROBOTS_FILE, SITEMAP_FILE},
+    template::{
+        comment_items, comment_threads, create_directory, create_subreddit, create_user, group_by_author, group_by_community, set_comment_links,
+        set_comment_prefs, sort_comments, subreddit_page_count, ArchiveFormat, InputFormat, PostTemplater, SortOrder, Thread, Threads, Timeframe,
+    },
+    util::{
+        archive_root, comment_page_path, mirror_path, multi_post_path, output_paths, page_url, parallel_map, subreddit_page_path, user_page_path, PostNames,
+    },
+    writer::{write_all, Site},
 };
+use redlib::bdfr::SubmissionArchiveEntry;
 use redlib::post::PostTemplate;
-use redlib::utils::{Comment, Post, Preferences};
+use redlib::utils::{available_themes, disable_indexing, Post, Preferences};
+
+/// How often `serve` checks the source directory for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Config for the generator (as well as the CLI parser itself)
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(
     version,
     about = "Generate static sites from Reddit archives",
@@ -30,16 +60,17 @@ struct Cli {
         short = 's',
         long = "source",
         value_name = "SOURCE",
-        required = true,
-        help = "Path to directory of JSON files to be parsed"
+        help = "Path to directory of input files to be parsed",
+        global = true,
     )]
-    source: String,
+    source: Option<String>,
 
     #[arg(
         short = 'o',
         long = "output",
         value_name = "OUTPUT",
         default_value = "out",
+        global = true,
     )]
     output: String,
 
@@ -49,43 +80,229 @@ struct Cli {
         value_name = "INPUT_FORMAT",
         help = "Format of files in the input directory.",
         default_value_t = InputFormat::BDFRSelfPost,
+        global = true,
     )]
     #[arg(value_enum)]
     input_format: InputFormat,
 
+    #[arg(
+        long = "archive-format",
+        value_name = "ARCHIVE_FORMAT",
+        help = "Format BDFR wrote the archives in, for bdfr-self-post inputs [default: by file extension]",
+        global = true,
+    )]
+    #[arg(value_enum)]
+    archive_format: Option<ArchiveFormat>,
+
     #[arg(
         short = 't',
         long = "title",
         value_name = "TITLE",
-        help = "Title for the generated site",
-        default_value = "Redlib Archive",
+        help = "Title for the generated site [default: Redlib Archive]",
+        global = true,
     )]
-    archive_title: String,
+    archive_title: Option<String>,
 
     #[arg(
         short = 'd',
         long = "desc",
         value_name = "DESCRIPTION",
-        help = "Description for the generated site",
-        default_value = "An archive of Reddit posts.",
+        help = "Description for the generated site [default: An archive of Reddit posts.]",
+        global = true,
+    )]
+    archive_desc: Option<String>,
+
+    #[arg(
+        long = "page-size",
+        value_name = "PAGE_SIZE",
+        help = "Number of posts on each page of a subreddit index [default: 25]",
+        global = true,
+    )]
+    page_size: Option<usize>,
+
+    #[arg(
+        long = "top",
+        value_name = "TIMEFRAME",
+        value_delimiter = ',',
+        help = "Timeframes to generate top post indexes for, besides all time",
+        global = true,
+    )]
+    #[arg(value_enum)]
+    top_timeframes: Vec<Timeframe>,
+
+    #[arg(
+        long = "base-url",
+        value_name = "URL",
+        help = "Absolute URL the site will be published at, used for links in feeds and sitemaps",
+        global = true,
     )]
-    archive_desc: String,
+    base_url: Option<String>,
+
+    #[arg(
+        long = "theme",
+        value_name = "THEME",
+        help = "Theme to bake into the site's stylesheet [default: system]",
+        global = true,
+    )]
+    theme: Option<String>,
+
+    #[arg(
+        short = 'j',
+        long = "jobs",
+        value_name = "JOBS",
+        help = "Number of worker threads [default: number of CPUs]",
+        global = true,
+    )]
+    jobs: Option<usize>,
+
+    #[arg(
+        long = "force",
+        help = "Rebuild every page, even if its input has not changed since the last run",
+        global = true,
+    )]
+    force: bool,
+
+    #[arg(
+        short = 'c',
+        long = "config",
+        value_name = "CONFIG",
+        help = "TOML file with the site's settings, overridden by the options given here",
+        global = true,
+    )]
+    config: Option<PathBuf>,
+
+    /// Settings loaded from `config`
+    #[arg(skip)]
+    site: SiteConfig,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Generate the site, serve it locally and rebuild it whenever the source changes
+    Serve {
+        #[arg(
+            short = 'a',
+            long = "address",
+            value_name = "ADDRESS",
+            help = "Address to listen on",
+            default_value = "127.0.0.1",
+        )]
+        address: String,
+
+        #[arg(
+            short = 'p',
+            long = "port",
+            value_name = "PORT",
+            help = "Port to listen on",
+            default_value_t = 8080,
+        )]
+        port: u16,
+    },
 }
 
 impl Cli {
+    /// Load the site config file, if one was given
+    pub fn load_config(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.config {
+            self.site = SiteConfig::load(path)?;
+        }
+        Ok(())
+    }
+
+    /// Directory of the input files, which `main()` makes sure was given
+    pub fn source(&self) -> &str {
+        self.source.as_deref().unwrap_or_default()
+    }
+
     /// Get template preferences for this configuration
     pub fn template_prefs(&self) -> Preferences {
-        let mut prefs = Preferences::default();
-        prefs.static_path = "static".to_string();
-        prefs.show_nsfw = "on".to_string();
-        prefs.disable_visit_reddit_confirmation = "on".to_string();
+        let on_off = |on: bool| if on { "on" } else { "off" }.to_string();
+        let defaults = Preferences::default();
+
+        Preferences {
+            static_path: "static".to_string(),
+            show_nsfw: on_off(self.site.show_nsfw.unwrap_or(true)),
+            blur_nsfw: on_off(self.site.blur_nsfw.unwrap_or(false)),
+            blur_spoiler: on_off(self.site.blur_spoiler.unwrap_or(false)),
+            layout: self.site.layout.clone().unwrap_or(defaults.layout.clone()),
+            comment_sort: self.comment_sort().to_string(),
+            disable_visit_reddit_confirmation: "on".to_string(),
+            theme: self.theme().to_string(),
+            archive_base_url: self.base_url().unwrap_or_default().to_string(),
+            ..defaults
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        self.archive_title.as_deref().or(self.site.title.as_deref()).unwrap_or("Redlib Archive")
+    }
+
+    pub fn description(&self) -> &str {
+        self.archive_desc.as_deref().or(self.site.description.as_deref()).unwrap_or("An archive of Reddit posts.")
+    }
+
+    /// Number of posts on each page of a subreddit index
+    pub fn page_size(&self) -> usize {
+        self.page_size.or(self.site.page_size).unwrap_or(25)
+    }
+
+    pub fn theme(&self) -> &str {
+        self.theme.as_deref().or(self.site.theme.as_deref()).unwrap_or("system")
+    }
+
+    /// Order of the comments on post pages, see `sort_comments()`
+    pub fn comment_sort(&self) -> &str {
+        self.site.comment_sort.as_deref().unwrap_or("confidence")
+    }
 
-        prefs
+    /// Number of worker threads to build the site with
+    pub fn workers(&self) -> usize {
+        self.jobs
+            .or_else(|| thread::available_parallelism().ok().map(usize::from))
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// Orders subreddit indexes are generated in
+    pub fn sort_orders(&self) -> Vec<SortOrder> {
+        SortOrder::all(&self.top_timeframes)
+    }
+
+    /// Extensions of the input files
+    pub fn input_extensions(&self) -> &'static [&'static str] {
+        match self.archive_format {
+            Some(format) => format.extensions(),
+            None => self.input_format.extensions(),
+        }
+    }
+
+    /// Base URL of the site, without a trailing slash
+    pub fn base_url(&self) -> Option<&str> {
+        self.base_url.as_deref().or(self.site.base_url.as_deref()).map(|url| url.trim_end_matches('/')).filter(|url| !url.is_empty())
+    }
+
+    /// Options that affect every generated page. Changing any of them
+    /// invalidates the previous build.
+    pub fn build_settings(&self) -> String {
+        format!(
+            "{:?}\n{}\n{}\n{}\n{:?}\n{}\n{}\n{:?}\n{}",
+            self.input_format,
+            self.title(),
+            self.description(),
+            self.page_size(),
+            self.sort_orders(),
+            self.base_url().unwrap_or_default(),
+            serde_urlencoded::to_string(self.template_prefs()).unwrap_or_default(),
+            self.site.filters,
+            self.site.mark_external_links,
+        )
     }
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
     // Load environment variables
 	_ = dotenvy::dotenv();
 
@@ -93,59 +310,402 @@ async fn main() {
 	pretty_env_logger::init();
 
     // Parse command line arguments
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    if cli.source.is_none() {
+        // Global so that it can follow `serve`, which keeps clap from requiring it
+        Cli::command()
+            .error(ErrorKind::MissingRequiredArgument, "the following required arguments were not provided:\n  --source <SOURCE>")
+            .exit();
+    }
+    if let Err(e) = cli.load_config() {
+        eprintln!("Error: {e}");
+        process::exit(1);
+    }
 
     // Run the generator
-    create_site(&cli).await.unwrap();
+    if let Err(e) = create_site(&cli) {
+        eprintln!("Error: {e}");
+        process::exit(1);
+    }
+
+    if let Some(Command::Serve { address, port }) = &cli.command {
+        if let Err(e) = preview(&cli, &format!("{address}:{port}")) {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Serve a generated site, rebuilding it in the background whenever its
+/// source or config file changes. Only changed posts are re-rendered.
+fn preview(config: &Cli, addr: &str) -> Result<(), Box<dyn Error>> {
+    // The first build has already been forced, if asked to
+    let watched = Cli {
+        force: false,
+        ..config.clone()
+    };
+    let mut paths = vec![canonicalize(watched.source())?];
+    paths.extend(watched.config.as_ref().map(canonicalize).transpose()?);
+    // The output directory may be inside the source tree
+    let exclude = [canonicalize(&watched.output)?];
+    thread::spawn(move || {
+        watch(&paths, &exclude, WATCH_INTERVAL, || {
+            println!("Source changed, rebuilding...");
+            let mut config = watched.clone();
+            if let Err(e) = config.load_config().and_then(|()| create_site(&config)) {
+                eprintln!("Error: {e}");
+            }
+        })
+    });
+
+    println!("Serving {} at http://{addr}/ (press Ctrl+C to stop)", &config.output);
+    tokio::runtime::Runtime::new()?.block_on(async { serve(PathBuf::from(&config.output), addr).await })?;
+
+    Ok(())
 }
 
 /// Run the site generator with a given config.
-async fn create_site(config: &Cli) -> Result<(), Box<dyn Error>> {
+fn create_site(config: &Cli) -> Result<(), Box<dyn Error>> {
     // Canonicalize paths
-    let src_path = canonicalize(&config.source)?;
-    let out_dir = PathBuf::try_from(&config.output)?;
+    let src_path = canonicalize(config.source())?;
+    let out_dir = PathBuf::from(&config.output);
 
     // Create output directory if it doesn't exist
     if !out_dir.exists() {
         create_dir_all(&config.output)?;
     }
 
+    let themes = available_themes();
+    if !themes.iter().any(|theme| theme == config.theme()) {
+        return Err(format!("unknown theme {:?}, expected one of: {}", config.theme(), themes.join(", ")).into());
+    }
+
+    if config.archive_format.is_some() && config.input_format != InputFormat::BDFRSelfPost {
+        return Err(format!("--archive-format only applies to {} inputs", InputFormat::BDFRSelfPost).into());
+    }
+
     let prefs = config.template_prefs();
 
+    // Pages from the previous run are only reused if they were generated by
+    // this build with the same settings
+    let settings = config.build_settings();
+    let previous = Manifest::load(&out_dir).unwrap_or_default();
+    let full_rebuild = config.force || !previous.is_compatible(&settings);
+    if full_rebuild && !previous.entries.is_empty() {
+        println!("Previous build is out of date, rebuilding everything...");
+    }
+    let mut manifest = Manifest::new(settings);
+
     println!("Indexing input files...");
-    let paths = json_paths_recursive(&src_path)?;
+    // The output directory is skipped, in case it is inside the source tree
+    let mut paths = input_paths_recursive(&src_path, config.input_extensions(), &canonicalize(&out_dir)?)?;
+    paths.sort();
+    // Only the inputs selected by the site config are built
+    let filter = config.site.input_filter()?;
+    paths.retain(|path| path.strip_prefix(&src_path).is_ok_and(|key| filter.matches(key)));
+    let media_index = MediaIndex::scan(&src_path, &canonicalize(&out_dir)?)?;
+    let dumps = match config.input_format {
+        InputFormat::Pushshift => Some(DumpIndex::new(&paths)?),
+        _ => None,
+    };
+    let workers = config.workers();
+    let standalone = match config.input_format {
+        InputFormat::BDFRSelfPost => {
+            // Archives are only read again if they changed since the last build
+            let no_cache = BTreeMap::new();
+            let cached = if full_rebuild { &no_cache } else { &previous.archives };
+            let index = StandaloneComments::scan(&paths, config.archive_format, cached, workers);
+            manifest.archives = index.archives().clone();
+            Some(index)
+        }
+        _ => None,
+    };
+
+    // Post pages mirror the source tree
+    let keys = paths
+        .iter()
+        .map(|path| path.strip_prefix(&src_path).map(Path::to_path_buf))
+        .collect::<Result<Vec<_>, _>>()?;
+    let pages = output_paths(&keys, &prefs.static_path, config.input_format.is_multi_post());
+    for (key, page) in keys.iter().zip(&pages) {
+        if *page != mirror_path(key) {
+            warn!("The page of {} would overwrite another page, writing it to {} instead", key.display(), page.display());
+        }
+    }
+    let sources: Vec<(&PathBuf, (&PathBuf, &PathBuf))> = paths.iter().zip(keys.iter().zip(&pages)).collect();
+
+    let reader = InputReader {
+        out_dir: &out_dir,
+        input_format: &config.input_format,
+        archive_format: config.archive_format,
+        filter: &config.site.filters,
+        media_index: &media_index,
+        dumps: dumps.as_ref(),
+        standalone: standalone.as_ref(),
+        previous: if full_rebuild { None } else { Some(&previous) },
+    };
+    let inputs = parallel_map("Building posts", &sources, workers, |(input_path, (key, page))| {
+        reader.read(input_path, key, page).map_err(|e| format!("{}: {e}", input_path.display()))
+    });
+    let mut inputs = inputs.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+    // Links between posts are rewritten once every page is known. Unchanged
+    // posts are rebuilt if a page they link to was added, moved or removed.
+    let link_index = LinkIndex::build(inputs.iter().flatten().filter_map(Input::entry));
+    let rebuild = InputReader { previous: None, ..reader };
+    for ((input_path, (key, page)), file_inputs) in sources.iter().zip(&mut inputs) {
+        let outdated = file_inputs
+            .iter()
+            .any(|input| matches!(input, Input::Unchanged(_, entry) if !link_index.is_current(&entry.links)));
+        if outdated {
+            *file_inputs = rebuild.read(input_path, key, page).map_err(|e| format!("{}: {e}", input_path.display()))?;
+        }
+    }
+    let rewriter = LinkRewriter {
+        index: &link_index,
+        mark_external: config.site.mark_external_links,
+    };
+
+    let mut posts = BTreeMap::new();
+    let mut media = Vec::new();
+    // Communities and authors whose pages have to be regenerated
+    let mut affected = BTreeSet::new();
+    let mut affected_authors = BTreeSet::new();
+    let mut filtered = FilterStats::default();
+    for input in inputs.into_iter().flatten() {
+        match input {
+            Input::Unchanged(key, entry) => {
+                manifest.entries.insert(key, *entry);
+            }
+            Input::Dropped(rule) => {
+                *filtered.dropped.entry(rule).or_default() += 1;
+            }
+            Input::Parsed(key, mut parsed) => {
+                parsed.resolve_links(&rewriter);
+                if let Some(old) = previous.entries.get(&key) {
+                    affected.insert(old.post.community.clone());
+                    affected_authors.extend(authors(old));
+                }
+                filtered.removed_comments += parsed.removed_comments;
+                affected.insert(parsed.entry.post.community.clone());
+                affected_authors.extend(authors(&parsed.entry));
+                manifest.entries.insert(key, parsed.entry);
+                posts.insert(parsed.out_path, parsed.thread);
+                media.extend(parsed.media);
+            }
+        }
+    }
+
+    // Remove pages and media whose input no longer exists
+    let outputs: HashSet<PathBuf> = manifest.entries.values().flat_map(ManifestEntry::outputs).collect();
+    for (key, entry) in &previous.entries {
+        if !manifest.entries.contains_key(key) {
+            affected.insert(entry.post.community.clone());
+            affected_authors.extend(authors(entry));
+        }
+        for output in entry.outputs() {
+            let path = out_dir.join(&output);
+            if !outputs.contains(&output) && path.exists() {
+                debug!("Removing {}", path.display());
+                remove_file(&path)?;
+                // Along with the directories of the source tree it leaves empty
+                for dir in path.ancestors().skip(1).take_while(|dir| *dir != out_dir) {
+                    if remove_dir(dir).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    for line in filtered.summary() {
+        println!("{line}");
+    }
+
+    if !full_rebuild && affected.is_empty() && affected_authors.is_empty() {
+        println!("Nothing to do, {} is up to date", &config.output);
+        return Ok(());
+    }
+
+    println!("Building subreddit pages...");
+    let groups = group_by_community(manifest.entries.values().map(|entry| entry.post.clone()).collect());
+    for community in affected.iter().filter(|community| !community.is_empty()) {
+        // Stale pages are removed, a shorter index may need fewer of them
+        let dir = out_dir.join("r").join(community);
+        if dir.exists() {
+            remove_dir_all(dir)?;
+        }
+    }
+    let orders = config.sort_orders();
+    let indexes = groups
+        .iter()
+        .filter(|(name, _)| full_rebuild || affected.contains(*name))
+        .flat_map(|(name, posts)| create_subreddit(name, posts, config.page_size(), &orders, &prefs))
+        .collect();
+    let browse = groups
+        .iter()
+        .filter(|(name, _)| full_rebuild || affected.contains(*name))
+        .flat_map(|(name, posts)| {
+            let mut pages = create_date_pages(name, posts, config.page_size(), &prefs);
+            pages.extend(create_flair_pages(name, posts, config.page_size(), &prefs));
+            pages
+        })
+        .collect();
+
+    let mut files = Vec::new();
+    if let Some(base_url) = config.base_url() {
+        println!("Building feeds...");
+        let mut posts: Vec<Post> = groups.values().flatten().cloned().collect();
+        posts.sort_by(|a, b| b.created_ts.cmp(&a.created_ts).then_with(|| a.id.cmp(&b.id)));
+        files.extend(
+            ArchiveFeed {
+                title: config.title().to_string(),
+                description: config.description().to_string(),
+                base_url,
+                dir: PathBuf::new(),
+                posts: &posts,
+            }
+            .files()?,
+        );
+        for (name, posts) in groups.iter().filter(|(name, _)| full_rebuild || affected.contains(*name)) {
+            files.extend(
+                ArchiveFeed {
+                    title: format!("r/{name}"),
+                    description: format!("Archived posts from r/{name}"),
+                    base_url,
+                    dir: PathBuf::from("r").join(name),
+                    posts,
+                }
+                .files()?,
+            );
+        }
+    } else {
+        // Remove feeds left over from a build with a base URL
+        for file in [RSS_FILE, ATOM_FILE] {
+            if out_dir.join(file).exists() {
+                remove_file(out_dir.join(file))?;
+            }
+        }
+    }
+
+    // Stale numbered sitemaps are removed, there may be fewer of them
+    for entry in read_dir(&out_dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if name == SITEMAP_FILE || (name.starts_with("sitemap-") && name.ends_with(".xml")) {
+            remove_file(out_dir.join(name))?;
+        }
+    }
+    files.push((PathBuf::from(ROBOTS_FILE), robots_txt(config.base_url(), disable_indexing())));
+    if !robots_txt_found(config.base_url()) {
+        warn!("Crawlers only read {ROBOTS_FILE} at the root of the host, move it there for its rules to apply");
+    }
+    if let Some(base_url) = config.base_url() {
+        println!("Building sitemap...");
+        let mut pages = vec![PathBuf::from("index.html")];
+        for (name, posts) in &groups {
+            pages.extend((1..=subreddit_page_count(posts.len(), config.page_size())).map(|page| subreddit_page_path(name, "", page)));
+        }
+        pages.extend(manifest.entries.values().map(|entry| entry.output.clone()));
+        files.extend(sitemaps(base_url, &pages));
+    }
 
-    println!("Building posts...");
-    let posts = create_posts(paths, &out_dir, &config.input_format)?;
+    println!("Building author pages...");
+    let authors = group_by_author(
+        manifest
+            .entries
+            .values()
+            .flat_map(|entry| once(&entry.post).chain(&entry.comments).cloned())
+            .collect(),
+    );
+    for author in &affected_authors {
+        // Stale pages are removed, a shorter listing may need fewer of them
+        let dir = out_dir.join("user").join(author);
+        if dir.is_dir() {
+            remove_dir_all(dir)?;
+        }
+        if authors.contains_key(author) {
+            continue;
+        }
+        for path in [user_page_path(author, 1), api_user_path(author)] {
+            let path = out_dir.join(path);
+            if path.exists() {
+                remove_file(path)?;
+            }
+        }
+    }
+    let users = authors
+        .iter()
+        .filter(|(name, _)| full_rebuild || affected_authors.contains(*name))
+        .flat_map(|(name, items)| create_user(name, items, config.page_size(), &prefs))
+        .collect();
+
+    println!("Building JSON API...");
+    files.push((PathBuf::from(API_INDEX_FILE), api_index(config.title(), manifest.entries.values())?));
+    for (path, (post, comments)) in posts.iter_mut() {
+        sort_comments(comments, &prefs.comment_sort);
+        let page = path.strip_prefix(&out_dir)?;
+        files.push((api_post_path(page), api_post(post, page, comments)?));
+    }
+    for (name, user) in api_users(manifest.entries.values()) {
+        if full_rebuild || affected_authors.contains(&name) {
+            files.push((api_user_path(&name), serde_json::to_string(&user)?));
+        }
+    }
 
-    println!("Building subreddit page...");
-    let sub = create_subreddit(
-        posts.iter().map(|(_, (post, _))| post.clone()).collect(),
-        &config.archive_title,
-        &config.archive_desc,
-        prefs.clone(),
-    )?;
+    let directory = create_directory(&groups, config.title(), config.description(), &prefs);
+
+    println!("Building search index...");
+    let search_index = SearchIndex::build(manifest.entries.values().map(|entry| &entry.search));
+    let search_page = SearchTemplate {
+        title: config.title().to_string(),
+        prefs: Preferences {
+            archive_root: archive_root(Path::new(SEARCH_PAGE_FILE)),
+            ..prefs.clone()
+        },
+        url: page_url(&prefs.archive_base_url, Path::new(SEARCH_PAGE_FILE)),
+    };
 
-    println!("Building post templates...");
-    let tmpls = build_post_templates(posts, prefs.clone())?;
+    let tmpls = build_post_templates(posts, &out_dir, prefs.clone())?;
 
     println!("Writing site files...");
-    write_all(tmpls, sub, &config.output, prefs.static_path).await?;
+    let site = Site {
+        posts: tmpls,
+        indexes,
+        browse,
+        users,
+        directory,
+        search_index,
+        search_page,
+        files,
+        media,
+        copy_static: full_rebuild || !out_dir.join(&prefs.static_path).exists(),
+    };
+    write_all(site, &config.output, &prefs.static_path, &prefs.theme, workers)?;
+
+    manifest.save(&out_dir)?;
 
     println!("Site generated at {}", &config.output);
 
     Ok(())
 }
 
-/// Resolve paths of all JSON files in a directory and its subdirectories
-fn json_paths_recursive(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+/// Authors of a post and its comments
+fn authors(entry: &ManifestEntry) -> impl Iterator<Item = String> + '_ {
+    once(&entry.post).chain(&entry.comments).map(|item| item.author.name.clone()).filter(|name| !name.is_empty())
+}
+
+/// Resolve paths of all files with one of `extensions` in a directory and its
+/// subdirectories, except `exclude`
+fn input_paths_recursive(path: &Path, extensions: &[&str], exclude: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
     let mut paths = Vec::new();
 
     for entry in read_dir(path)? {
         let path = entry?.path();
-        if path.is_dir() && !path.starts_with(".") {
-            paths.extend(json_paths_recursive(&path)?);
-        } else if path.extension().is_some_and(|ext| ext == "json") {
+        if path == exclude {
+            continue;
+        } else if path.is_dir() && !path.starts_with(".") {
+            paths.extend(input_paths_recursive(&path, extensions, exclude)?);
+        } else if path.extension().is_some_and(|ext| extensions.iter().any(|extension| ext == *extension)) {
             paths.push(path);
         }
     }
@@ -153,67 +713,242 @@ fn json_paths_recursive(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
     Ok(paths)
 }
 
-/// Create PostTemplate objects from JSON files
-fn create_posts(
-    paths: Vec<PathBuf>,
-    out_dir: &PathBuf,
-    input_format: &InputFormat,
-) -> Result<HashMap<PathBuf, (Post, Vec<Comment>)>, Box<dyn Error>> {
-    let mut map = HashMap::new();
+/// Result of reading one input file
+enum Input {
+    /// The page from the previous build is up to date, keyed by input path
+    Unchanged(PathBuf, Box<ManifestEntry>),
+    /// The post has to be (re)built, keyed by input path
+    Parsed(PathBuf, Box<ParsedInput>),
+    /// The post was left out by a content filter
+    Dropped(FilterRule),
+}
 
-    for input_path in paths {
-        let (k, v) = create_post(&input_path, &out_dir, &input_format)?;
-        map.insert(k, v);
+impl Input {
+    /// Manifest entry of a post that is part of the build
+    fn entry(&self) -> Option<&ManifestEntry> {
+        match self {
+            Input::Unchanged(_, entry) => Some(entry),
+            Input::Parsed(_, parsed) => Some(&parsed.entry),
+            Input::Dropped(_) => None,
+        }
     }
+}
 
-    Ok(map)
+struct ParsedInput {
+    entry: ManifestEntry,
+    out_path: PathBuf,
+    thread: Thread,
+    /// Media to copy, see `localize()`
+    media: Vec<(PathBuf, PathBuf)>,
+    /// Number of deleted comments left out, see `ContentFilter::filter_comments()`
+    removed_comments: usize,
 }
 
-fn create_post(
-    input_path: &PathBuf,
-    out_dir: &PathBuf, 
-    input_format: &InputFormat,
-) -> Result<(PathBuf, (Post, Vec<Comment>)), Box<dyn Error>> {
-    println!("Creating template for {}", input_path.display());
-    
-    // Resolve output path
-    println!("Resolving output path");
-    let out_path = output_path(input_path, out_dir, "html".to_string())?;
-
-    // Read JSON file
-    println!("Reading JSON data into post");
-    let json: Value = serde_json::from_reader(File::open(input_path)?)?;
-
-    Ok(match input_format {
-        InputFormat::BDFRSelfPost => {
-            let subm: SubmissionArchiveEntry = serde_json::from_value(json)?;
-            let mut post = subm.to_post()?;
-            post.permalink = out_path.file_name().unwrap().to_string_lossy().to_string();
-            let comments = subm.comments();
+impl ParsedInput {
+    /// Rewrite the links of the post and its comments, see `LinkRewriter`
+    fn resolve_links(&mut self, rewriter: &LinkRewriter) {
+        let (post, comments) = &mut self.thread;
+        let mut links = BTreeMap::new();
+        rewriter.rewrite_post(post, &mut links);
+        rewriter.rewrite_comments(comments, &mut links);
+
+        self.entry.post = post.clone();
+        self.entry.comments = comment_items(post, &self.entry.output, comments);
+        self.entry.links = links;
+    }
+}
 
-            (out_path, (post, comments))
-        },
-        InputFormat::RedditJson => todo!(),
-    })
+/// Settings shared by every input of a build
+struct InputReader<'a> {
+    out_dir: &'a Path,
+    input_format: &'a InputFormat,
+    /// Format of BDFR archives, unless it is told by their extensions
+    archive_format: Option<ArchiveFormat>,
+    filter: &'a ContentFilter,
+    media_index: &'a MediaIndex,
+    /// Dumps of the build, if it reads `InputFormat::Pushshift` inputs
+    dumps: Option<&'a DumpIndex>,
+    /// Comments BDFR archived on their own, if it reads BDFR archives
+    standalone: Option<&'a StandaloneComments>,
+    /// Manifest of the previous build, unless everything is rebuilt
+    previous: Option<&'a Manifest>,
+}
+
+impl InputReader<'_> {
+    /// Hash an input file and parse its posts, unless the previous build has
+    /// up to date pages for them. `key` is the input's path relative to the
+    /// source directory, `page` the path of its page relative to the archive
+    /// root. Posts of inputs that hold several are keyed by `key/<name>` and
+    /// get their pages from `multi_post_path()`, see `PostNames`.
+    fn read(&self, input_path: &Path, key: &Path, page: &Path) -> Result<Vec<Input>, Box<dyn Error>> {
+        let InputReader { out_dir, input_format, filter, media_index, dumps, standalone, previous, .. } = *self;
+        // Inputs were already hashed if they were indexed for standalone comments
+        let file_hash = || match standalone.and_then(|index| index.hash(input_path)) {
+            Some(hash) => Ok(hash.to_string()),
+            None => hash_file(input_path),
+        };
+        let standalone = standalone.map_or(StandaloneInput::Post(None), |index| index.input(input_path));
+        let hash = match (input_format, dumps, &standalone) {
+            (_, Some(dumps), _) => dumps.fingerprint().to_string(),
+            (InputFormat::GdprExport, _, _) => export_hash(input_path)?,
+            // Comments built with their submission are hashed along with it
+            (_, _, StandaloneInput::Merged) => return Ok(Vec::new()),
+            (_, _, StandaloneInput::Placeholder(group)) => group.hash.clone(),
+            (_, _, StandaloneInput::Post(Some(group))) => format!("{}+{}", file_hash()?, group.hash),
+            _ => file_hash()?,
+        };
+        let post_page = |name: &str| if input_format.is_multi_post() { multi_post_path(page, name) } else { page.to_path_buf() };
+
+        // Posts of the input in the previous build
+        let cached: Vec<(&PathBuf, &ManifestEntry)> = match previous {
+            Some(manifest) if input_format.is_multi_post() => manifest
+                .entries
+                .range(key.to_path_buf()..)
+                .take_while(|(entry_key, _)| entry_key.starts_with(key))
+                .filter(|(entry_key, _)| entry_key.parent() == Some(key))
+                .collect(),
+            Some(manifest) => manifest.entries.get_key_value(key).into_iter().collect(),
+            None => Vec::new(),
+        };
+        let mut unchanged = !cached.is_empty();
+        for (entry_key, entry) in &cached {
+            unchanged = unchanged
+                && entry.hash == hash
+                && entry.output == post_page(&entry_key.file_name().unwrap_or_default().to_string_lossy())
+                && entry.media_hash == fingerprint(media_index.root(), &media_index.find(&entry.post.id, input_path))?
+                && out_dir.join(&entry.output).exists();
+        }
+        if unchanged {
+            return Ok(cached
+                .into_iter()
+                .map(|(key, entry)| Input::Unchanged(key.clone(), Box::new(entry.clone())))
+                .collect());
+        }
+
+        let mut inputs = Vec::new();
+        let mut names = PostNames::default();
+        for thread in self.threads(input_path, &standalone)? {
+            let (mut post, mut comments) = thread?;
+            let name = names.name(&post.id);
+            let post_key = if input_format.is_multi_post() { key.join(&name) } else { key.to_path_buf() };
+            let page = post_page(&name);
+            if name != post.id {
+                warn!("{} holds post {} more than once, writing it to {} as well", key.display(), post.id, page.display());
+            }
+            post.permalink = page_url("", &page);
+            if let Some(rule) = filter.check(&post) {
+                inputs.push(Input::Dropped(rule));
+                continue;
+            }
+            let removed_comments = filter.filter_comments(&mut comments);
+
+            // Use local copies of downloaded media
+            let files = media_index.find(&post.id, input_path);
+            let media = localize(&mut post, media_index.root(), &files);
+
+            let entry = ManifestEntry {
+                hash: hash.clone(),
+                output: page.clone(),
+                media: media.iter().map(|(_, dest)| dest.clone()).collect(),
+                media_hash: fingerprint(media_index.root(), &files)?,
+                post: post.clone(),
+                comments: comment_items(&post, &page, &comments),
+                search: SearchEntry::new(&post, &comments),
+                links: BTreeMap::new(),
+            };
+
+            inputs.push(Input::Parsed(post_key, Box::new(ParsedInput {
+                entry,
+                out_path: out_dir.join(&page),
+                thread: (post, comments),
+                media,
+                removed_comments,
+            })));
+        }
+        Ok(inputs)
+    }
+
+    /// Create the posts of an input file and their comments
+    fn threads(&self, input_path: &Path, standalone: &StandaloneInput) -> Result<Threads<'_>, Box<dyn Error>> {
+        debug!("Creating templates for {}", input_path.display());
+
+        let threads = match (self.input_format, self.dumps, standalone) {
+            (_, Some(dumps), _) => return dumps.threads(input_path),
+            (InputFormat::GdprExport, _, _) => gdpr::threads(input_path),
+            (InputFormat::BDFRSelfPost, _, StandaloneInput::Placeholder(group)) => {
+                let entry = SubmissionArchiveEntry::placeholder(group.entries(self.archive_format)?).ok_or("expected comments")?;
+                Ok(vec![entry.thread()?])
+            }
+            (InputFormat::BDFRSelfPost, _, StandaloneInput::Post(group)) => {
+                let mut entry: SubmissionArchiveEntry = serde_json::from_value(ArchiveFormat::read(input_path, self.archive_format)?)?;
+                if let Some(group) = group {
+                    entry.add_comments(group.entries(self.archive_format)?);
+                }
+                Ok(vec![entry.thread()?])
+            }
+            (_, _, StandaloneInput::Merged) => Ok(Vec::new()),
+            _ => {
+                let json: Value = serde_json::from_reader(BufReader::new(File::open(input_path)?))?;
+                self.input_format.threads(json)
+            }
+        }?;
+        Ok(Box::new(threads.into_iter().map(Ok)))
+    }
 }
 
+/// Post pages and comment permalink pages of the parsed posts, whose comments
+/// are already sorted
 fn build_post_templates(
-    posts: HashMap<PathBuf, (Post, Vec<Comment>)>,
+    posts: BTreeMap<PathBuf, Thread>,
+    out_dir: &Path,
     prefs: Preferences,
-) -> Result<HashMap<PathBuf, PostTemplate>, Box<dyn Error>> {
-    let mut map = HashMap::new();
+) -> Result<Vec<(PathBuf, PostTemplate)>, Box<dyn Error>> {
+    let mut tmpls = Vec::new();
+
+    for (path, (post, mut comments)) in posts {
+        let page = path.strip_prefix(out_dir)?;
+        let prefs = Preferences {
+            archive_root: archive_root(page),
+            ..prefs.clone()
+        };
+        let url = page_url(&prefs.archive_base_url, page);
+        set_comment_prefs(&mut comments, &prefs);
+        set_comment_links(&mut comments, page);
+
+        // Permalink pages of the comments, each with its thread. The text of
+        // the post is only shown on its own page.
+        let thread_post = Post {
+            body: String::new(),
+            ..post.clone()
+        };
+        for (id, mut thread) in comment_threads(&mut comments) {
+            let comment_page = comment_page_path(page, &id);
+            let prefs = Preferences {
+                archive_root: archive_root(&comment_page),
+                ..prefs.clone()
+            };
+            set_comment_prefs(slice::from_mut(&mut thread), &prefs);
+            tmpls.push((out_dir.join(&comment_page), PostTemplate::new(
+                thread_post.clone(),
+                vec![thread],
+                prefs.comment_sort.clone(),
+                prefs.clone(),
+                true,
+                page_url(&prefs.archive_base_url, &comment_page),
+                "".to_string(),
+            )));
+        }
 
-    for (path, (post, comments)) in posts {
-        map.insert(path, PostTemplate::new(
+        tmpls.push((path, PostTemplate::new(
             post,
             comments,
-            "new".to_string(),
-            prefs.clone(),
-            true,
-            "".to_string(),
+            prefs.comment_sort.clone(),
+            prefs,
+            false,
+            url,
             "".to_string(),
-        ));
+        )));
     }
 
-    Ok(map)
+    Ok(tmpls)
 }
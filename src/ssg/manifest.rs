@@ -0,0 +1,131 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Build manifest for incremental rebuilds. Each generated archive records the
+// hash of every input file along with everything needed to rebuild indexes
+// without re-parsing it, so later runs only have to render what changed.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::ssg::api::api_post_path;
+use crate::ssg::search::SearchEntry;
+use crate::ssg::standalone::Archive;
+use crate::ssg::util::comment_page_path;
+use crate::utils::Post;
+
+/// Name of the manifest, relative to the archive root
+pub const MANIFEST_FILE: &str = ".ssg-manifest.json";
+
+/// Version of the templates and static files compiled into this build. Pages
+/// written by a different build are never reused.
+pub fn build_version() -> String {
+    format!("{}+{}", env!("CARGO_PKG_VERSION"), env!("GIT_HASH").trim())
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Build that generated the archive, see `build_version()`
+    pub version: String,
+    /// Options the archive was generated with that affect every page
+    pub settings: String,
+    /// Input files, keyed by their path relative to the source directory
+    pub entries: BTreeMap<PathBuf, ManifestEntry>,
+    /// What the BDFR archives of the build hold, keyed by their hash, see
+    /// `StandaloneComments::scan()`
+    pub archives: BTreeMap<String, Archive>,
+}
+
+/// A generated post page and the input it was generated from
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// SHA-256 of the input file
+    pub hash: String,
+    /// Post page, relative to the archive root
+    pub output: PathBuf,
+    /// Local copies of the post's media, relative to the archive root
+    pub media: Vec<PathBuf>,
+    /// Fingerprint of the source media files, see `media::fingerprint()`
+    pub media_hash: String,
+    /// The post as shown in indexes
+    pub post: Post,
+    /// The post's comments as shown on their authors' pages
+    pub comments: Vec<Post>,
+    pub search: SearchEntry,
+    /// Reddit threads linked to by the post and its comments, with the
+    /// archived page each link points to, see `LinkIndex::is_current()`
+    pub links: BTreeMap<String, String>,
+}
+
+impl ManifestEntry {
+    /// Every file generated for the post: its page, the permalink pages of its
+    /// comments, its API file and its media, relative to the archive root
+    pub fn outputs(&self) -> Vec<PathBuf> {
+        let mut outputs = vec![self.output.clone(), api_post_path(&self.output)];
+        outputs.extend(self.comments.iter().map(|comment| comment_page_path(&self.output, &comment.id)));
+        outputs.extend(self.media.iter().cloned());
+        outputs
+    }
+}
+
+impl Manifest {
+    pub fn new(settings: String) -> Self {
+        Self {
+            version: build_version(),
+            settings,
+            entries: BTreeMap::new(),
+            archives: BTreeMap::new(),
+        }
+    }
+
+    /// Load the manifest of an existing archive, if it has a readable one.
+    pub fn load(out_dir: &Path) -> Option<Self> {
+        let file = File::open(out_dir.join(MANIFEST_FILE)).ok()?;
+        match serde_json::from_reader(BufReader::new(file)) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                warn!("Ignoring unreadable build manifest: {e}");
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+        let file = File::create(out_dir.join(MANIFEST_FILE))?;
+        serde_json::to_writer(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Whether pages from this manifest can be reused by a build with the
+    /// given settings.
+    pub fn is_compatible(&self, settings: &str) -> bool {
+        self.version == build_version() && self.settings == settings
+    }
+}
+
+/// Hex-encoded SHA-256 of a file's contents
+pub fn hash_file(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_compatible() {
+        let manifest = Manifest::new("bdfr|25".to_string());
+        assert!(manifest.is_compatible("bdfr|25"));
+        assert!(!manifest.is_compatible("bdfr|50"));
+        assert!(!Manifest::default().is_compatible("bdfr|25"));
+    }
+}
@@ -0,0 +1,276 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Local copies of downloaded media. BDFR saves the media of a submission next
+// to its archive JSON, named after the submission ID (`{REDDITOR}_{TITLE}_{POSTID}`
+// by default), with an `_<n>` suffix for each file of a gallery.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+
+use crate::ssg::util::page_url;
+use crate::utils::{GalleryMedia, Media, Post};
+
+/// Directory media is copied into, relative to the archive root
+pub const MEDIA_DIR: &str = "media";
+
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "webp", "gif", "bmp"];
+const VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "webm", "mov", "m4v"];
+
+/// Media files in a source tree, keyed by the submission ID in their name
+#[derive(Default)]
+pub struct MediaIndex {
+    root: PathBuf,
+    files: HashMap<String, Vec<(usize, PathBuf)>>,
+}
+
+impl MediaIndex {
+    /// Find all media files under a directory, except `exclude`.
+    pub fn scan(dir: &Path, exclude: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut index = Self {
+            root: dir.to_path_buf(),
+            ..Self::default()
+        };
+        index.scan_dir(dir, exclude)?;
+        for files in index.files.values_mut() {
+            files.sort();
+        }
+        Ok(index)
+    }
+
+    fn scan_dir(&mut self, dir: &Path, exclude: &Path) -> Result<(), Box<dyn Error>> {
+        for entry in read_dir(dir)? {
+            let path = entry?.path();
+            if path == exclude {
+                continue;
+            } else if path.is_dir() {
+                self.scan_dir(&path, exclude)?;
+            } else if media_kind(&path).is_some() {
+                self.insert(path);
+            }
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, path: PathBuf) {
+        let Some(stem) = path.file_stem().map(|stem| stem.to_string_lossy().to_string()) else {
+            return;
+        };
+        for (id, n) in submission_ids(&stem) {
+            self.files.entry(id).or_default().push((n, path.clone()));
+        }
+    }
+
+    /// Media files of the submission archived in `input`, in gallery order.
+    /// Files next to the input are preferred over those elsewhere in the
+    /// tree, and only one directory is used, so copies are left out. Only
+    /// files of one kind, numbered as a gallery, make a gallery; otherwise a
+    /// single file is picked, a video over an image.
+    pub fn find(&self, id: &str, input: &Path) -> Vec<&Path> {
+        let Some(files) = self.files.get(id) else {
+            return Vec::new();
+        };
+        // Files of one directory only, so copies elsewhere are not picked twice
+        let dir = if files.iter().any(|(_, path)| path.parent() == input.parent()) {
+            input.parent()
+        } else {
+            files.first().and_then(|(_, path)| path.parent())
+        };
+        let files: Vec<&(usize, PathBuf)> = files.iter().filter(|(_, path)| path.parent() == dir).collect();
+        let is_gallery = files.len() > 1
+            && files.iter().all(|(n, path)| *n > 0 && media_kind(path) == media_kind(&files[0].1));
+        if is_gallery || files.len() <= 1 {
+            return files.into_iter().map(|(_, path)| path.as_path()).collect();
+        }
+        let single = files.iter().find(|(_, path)| media_kind(path) == Some("video")).unwrap_or(&files[0]);
+        vec![single.1.as_path()]
+    }
+
+    /// Directory the media files were found in
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+/// Where a media file is copied to, relative to the archive root. Copies
+/// mirror the source tree, so that files of the same name in different
+/// directories are kept apart.
+fn media_path(root: &Path, file: &Path) -> Option<PathBuf> {
+    let relative = match file.strip_prefix(root) {
+        Ok(relative) => relative,
+        Err(_) => Path::new(file.file_name()?),
+    };
+    Some(Path::new(MEDIA_DIR).join(relative))
+}
+
+/// Submission IDs a media file could belong to, along with its position in a
+/// gallery. IDs never contain underscores, so a numeric last segment is
+/// either an ID or a gallery index.
+fn submission_ids(stem: &str) -> Vec<(String, usize)> {
+    let (rest, last) = stem.rsplit_once('_').unwrap_or(("", stem));
+    let mut ids = vec![(last.to_string(), 0)];
+    if let (Ok(n), Some(id)) = (last.parse(), rest.rsplit('_').next().filter(|id| !id.is_empty())) {
+        ids.push((id.to_string(), n));
+    }
+    ids
+}
+
+/// Post type a media file is shown as, if it is one
+pub fn media_kind(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    if IMAGE_EXTENSIONS.contains(&ext.as_str()) {
+        Some("image")
+    } else if VIDEO_EXTENSIONS.contains(&ext.as_str()) {
+        Some("video")
+    } else {
+        None
+    }
+}
+
+/// Point a post at local copies of its media, found under `root`. Returns the
+/// files to copy, along with their destination relative to the archive root.
+pub fn localize(post: &mut Post, root: &Path, files: &[&Path]) -> Vec<(PathBuf, PathBuf)> {
+    let copies: Vec<(PathBuf, PathBuf)> = files.iter().filter_map(|file| Some((file.to_path_buf(), media_path(root, file)?))).collect();
+    let href = |dest: &Path| page_url("", dest);
+
+    match copies.as_slice() {
+        [] => {}
+        [(src, dest)] => {
+            let kind = media_kind(src).unwrap_or("image");
+            // Reddit serves GIFs as videos, so a GIF post downloaded as one
+            // is still shown as a GIF
+            post.post_type = match kind {
+                "video" if is_gif_url(&post.media.url) => "gif",
+                kind => kind,
+            }
+            .to_string();
+            post.media = local_media(href(dest), dest);
+            post.thumbnail = if kind == "image" { post.media.clone() } else { Media::default() };
+        }
+        _ => {
+            post.post_type = "gallery".to_string();
+            post.gallery = copies
+                .iter()
+                .map(|(_, dest)| GalleryMedia {
+                    url: href(dest),
+                    width: 0,
+                    height: 0,
+                    caption: String::new(),
+                    outbound_url: String::new(),
+                })
+                .collect();
+            post.media = local_media(href(&copies[0].1), &copies[0].1);
+            post.thumbnail = post.media.clone();
+        }
+    }
+
+    copies
+}
+
+/// Cheap fingerprint of a post's media files under `root`, from their paths
+/// and sizes
+pub fn fingerprint(root: &Path, files: &[&Path]) -> Result<String, Box<dyn Error>> {
+    let mut parts = Vec::new();
+    for file in files {
+        parts.push(format!("{}:{}", file.strip_prefix(root).unwrap_or(file).to_string_lossy(), file.metadata()?.len()));
+    }
+    Ok(parts.join("/"))
+}
+
+fn local_media(url: String, dest: &Path) -> Media {
+    Media {
+        url,
+        alt_url: String::new(),
+        width: 0,
+        height: 0,
+        poster: String::new(),
+        download_name: dest.file_name().unwrap_or_default().to_string_lossy().to_string(),
+    }
+}
+
+fn is_gif_url(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or_default().to_lowercase();
+    path.ends_with(".gif") || path.ends_with(".gifv")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::parse_post;
+    use futures_lite::future::block_on;
+    use serde_json::json;
+
+    #[test]
+    fn test_submission_ids() {
+        assert_eq!(submission_ids("abc123"), vec![("abc123".to_string(), 0)]);
+        assert_eq!(submission_ids("user_Some_title_abc123"), vec![("abc123".to_string(), 0)]);
+        assert_eq!(
+            submission_ids("user_Some_title_abc123_2"),
+            vec![("2".to_string(), 0), ("abc123".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let mut index = MediaIndex::default();
+        for path in [
+            "/src/rust/ferris_Title_abc123.mp4",
+            "/src/rust/ferris_Title_abc123.jpg",
+            "/src/old/ferris_Title_abc123.mp4",
+            "/src/old/ferris_Title_abc123.png",
+            "/src/a/user_Gallery_def456_1.jpg",
+            "/src/a/user_Gallery_def456_2.png",
+            "/src/b/user_Gallery_def456_1.jpg",
+            "/src/b/user_Gallery_def456_2.jpg",
+        ] {
+            index.insert(PathBuf::from(path));
+        }
+        for files in index.files.values_mut() {
+            files.sort();
+        }
+
+        // Files next to the input win, and a video over its thumbnail
+        assert_eq!(index.find("abc123", Path::new("/src/rust/post.json")), vec![Path::new("/src/rust/ferris_Title_abc123.mp4")]);
+        // A copy in another directory does not make a gallery
+        assert_eq!(index.find("abc123", Path::new("/src/other/post.json")), vec![Path::new("/src/old/ferris_Title_abc123.mp4")]);
+        // Galleries are numbered files of the same kind
+        assert_eq!(index.find("def456", Path::new("/src/a/post.json")), vec![
+            Path::new("/src/a/user_Gallery_def456_1.jpg"),
+            Path::new("/src/a/user_Gallery_def456_2.png"),
+        ]);
+        assert_eq!(index.find("def456", Path::new("/src/c/post.json")), vec![
+            Path::new("/src/a/user_Gallery_def456_1.jpg"),
+            Path::new("/src/a/user_Gallery_def456_2.png"),
+        ]);
+        assert!(index.find("zzz999", Path::new("/src/rust/post.json")).is_empty());
+    }
+
+    #[test]
+    fn test_localize() {
+        let mut post = block_on(parse_post(&json!({"data": {"id": "abc123", "url": "https://i.imgur.com/x.gifv"}})));
+        let copies = localize(&mut post, Path::new("/src"), &[Path::new("/src/rust/user_title_abc123.mp4")]);
+        assert_eq!(copies, vec![(PathBuf::from("/src/rust/user_title_abc123.mp4"), PathBuf::from("media/rust/user_title_abc123.mp4"))]);
+        assert_eq!(post.post_type, "gif");
+        assert_eq!(post.media.url, "media/rust/user_title_abc123.mp4");
+
+        let mut post = block_on(parse_post(&json!({"data": {"id": "abc123"}})));
+        localize(&mut post, Path::new("/src"), &[Path::new("/src/abc123_1.jpg"), Path::new("/src/abc123_2.png")]);
+        assert_eq!(post.post_type, "gallery");
+        assert_eq!(post.gallery.len(), 2);
+        assert_eq!(post.gallery[1].url, "media/abc123_2.png");
+        assert_eq!(post.thumbnail.url, "media/abc123_1.jpg");
+
+        // Files of the same name in different directories are kept apart
+        let copies = localize(&mut post, Path::new("/src"), &[Path::new("/src/a/abc123.jpg"), Path::new("/src/b/abc123.jpg")]);
+        assert_eq!(copies[0].1, PathBuf::from("media/a/abc123.jpg"));
+        assert_eq!(copies[1].1, PathBuf::from("media/b/abc123.jpg"));
+
+        // Links to media named after the post's title are percent-encoded
+        let mut post = block_on(parse_post(&json!({"data": {"id": "abc123"}})));
+        localize(&mut post, Path::new("/src"), &[Path::new("/src/user_Is this #1?_abc123.jpg")]);
+        assert_eq!(post.media.url, "media/user_Is%20this%20%231%3F_abc123.jpg");
+        assert_eq!(post.media.download_name, "user_Is this #1?_abc123.jpg");
+    }
+}
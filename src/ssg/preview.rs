@@ -0,0 +1,156 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Local preview of a static archive: the output directory is served with the
+// same server as the live site, and the source directory is polled for
+// changes so the archive can be rebuilt while it is being served.
+
+use std::collections::BTreeMap;
+use std::fs::{metadata, read, read_dir};
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+use futures_lite::{future::Boxed, FutureExt};
+use hyper::{Body, Request, Response};
+use percent_encoding::percent_decode_str;
+
+use crate::headers;
+use crate::server::{RequestExt, Server};
+
+/// Directory being served. Routes are plain functions, so it is global.
+static SERVE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Serve a directory of static files on an address like `127.0.0.1:8080`.
+pub fn serve(dir: PathBuf, addr: &str) -> Boxed<Result<(), hyper::Error>> {
+    SERVE_DIR.get_or_init(|| dir);
+
+    let mut app = Server::new();
+    app.default_headers = headers! {
+        "Referrer-Policy" => "no-referrer",
+        "X-Content-Type-Options" => "nosniff",
+        "Cache-Control" => "no-cache"
+    };
+    app.at("/").get(|req| file(req).boxed());
+    app.at("/*path").get(|req| file(req).boxed());
+
+    app.listen(addr)
+}
+
+async fn file(req: Request<Body>) -> Result<Response<Body>, String> {
+    let path = req.param("path").unwrap_or_default();
+    let found = SERVE_DIR.get().and_then(|dir| resolve(dir, &percent_decode_str(&path).decode_utf8_lossy()));
+
+    let res = match found.and_then(|path| Some((read(&path).ok()?, path))) {
+        Some((body, path)) => Response::builder()
+            .status(200)
+            .header("content-type", content_type(&path))
+            .body(body.into()),
+        None => Response::builder()
+            .status(404)
+            .header("content-type", "text/plain")
+            .body(format!("{path} was not found in the archive").into()),
+    };
+
+    res.map_err(|e| e.to_string())
+}
+
+/// File a request path refers to, if it exists in `dir`. Directories are
+/// served by their `index.html`.
+pub fn resolve(dir: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+
+    let mut file = dir.join(relative);
+    if file.is_dir() {
+        file.push("index.html");
+    }
+    file.is_file().then_some(file)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().unwrap_or_default().to_string_lossy().to_lowercase().as_str() {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Modification time and size of every file in a directory tree
+type Snapshot = BTreeMap<PathBuf, (SystemTime, u64)>;
+
+/// Add a file, or every file of a directory tree, to a snapshot, skipping
+/// anything under `exclude`
+fn snapshot(path: &Path, exclude: &[PathBuf], files: &mut Snapshot) {
+    if exclude.iter().any(|excluded| path.starts_with(excluded)) {
+        return;
+    }
+    if !path.is_dir() {
+        if let Ok(meta) = metadata(path) {
+            files.insert(path.to_path_buf(), (meta.modified().unwrap_or(SystemTime::UNIX_EPOCH), meta.len()));
+        }
+        return;
+    }
+    let Ok(entries) = read_dir(path) else {
+        return;
+    };
+    for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+        snapshot(&path, exclude, files);
+    }
+}
+
+/// Check files and directory trees for added, changed or removed files every
+/// `interval`, and call `on_change` whenever there are any. Paths under
+/// `exclude` are ignored. Never returns.
+pub fn watch(paths: &[PathBuf], exclude: &[PathBuf], interval: Duration, mut on_change: impl FnMut()) {
+    let take = || {
+        let mut files = Snapshot::new();
+        for path in paths {
+            snapshot(path, exclude, &mut files);
+        }
+        files
+    };
+    let mut last = take();
+
+    loop {
+        sleep(interval);
+        let current = take();
+        if current != last {
+            on_change();
+            last = current;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        assert_eq!(resolve(dir, "/Cargo.toml"), Some(dir.join("Cargo.toml")));
+        assert_eq!(resolve(dir, "/static/"), None);
+        assert_eq!(resolve(dir, "/src/../Cargo.toml"), None);
+        assert_eq!(resolve(dir, "/missing.html"), None);
+        assert_eq!(content_type(Path::new("r/rust/index.html")), "text/html; charset=utf-8");
+    }
+}
@@ -0,0 +1,189 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Offline search for static archives. The index is written as a script that
+// assigns a global (rather than as plain JSON) so that the search page keeps
+// working when the archive is opened straight from disk, where `fetch()` of
+// local files is blocked by browsers.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rinja::Template;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{Comment, Post, Preferences};
+
+/// Name of the prebuilt index, relative to the archive root
+pub const SEARCH_INDEX_FILE: &str = "search-index.js";
+
+/// Name of the static search page, relative to the archive root
+pub const SEARCH_PAGE_FILE: &str = "search.html";
+
+/// Tokens shorter than this are not indexed
+const MIN_TOKEN_LEN: usize = 2;
+
+/// Tokens longer than this (hashes, URLs, ...) are not indexed
+const MAX_TOKEN_LEN: usize = 32;
+
+static HTML_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// Runs of characters between tokens: anything but letters, combining marks,
+/// numbers and `_`. Must match `TOKEN_SEPARATOR` in `static/archiveSearch.js`.
+static TOKEN_SEPARATOR: Lazy<Regex> = Lazy::new(|| Regex::new(r"[^\p{L}\p{M}\p{N}_]+").unwrap());
+
+/// Static page that queries the prebuilt search index in the browser.
+#[derive(Template)]
+#[template(path = "archive_search.html")]
+pub struct SearchTemplate {
+    pub title: String,
+    pub prefs: Preferences,
+    pub url: String,
+}
+
+/// A search result, as shown on the search page.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SearchDoc {
+    /// Post title
+    #[serde(rename = "t")]
+    pub title: String,
+    /// Link to the post, relative to the archive root
+    #[serde(rename = "u")]
+    pub url: String,
+    /// Subreddit name
+    #[serde(rename = "s")]
+    pub community: String,
+    /// Post author
+    #[serde(rename = "a")]
+    pub author: String,
+    /// Link flair text
+    #[serde(rename = "f")]
+    pub flair: String,
+    /// Date the post was created
+    #[serde(rename = "d")]
+    pub created: String,
+}
+
+/// A post's search result along with the tokens it can be found by. Entries
+/// are cached between builds so the index can be rebuilt without re-parsing
+/// unchanged posts.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SearchEntry {
+    pub doc: SearchDoc,
+    /// Unix timestamp of the post, used to order results
+    pub created_ts: u64,
+    /// Distinct tokens, space-separated
+    pub tokens: String,
+}
+
+impl SearchEntry {
+    /// Collect the searchable text of a post and its comments.
+    pub fn new(post: &Post, comments: &[Comment]) -> Self {
+        let mut tokens = BTreeSet::new();
+        tokens.extend(tokenize(&post.title));
+        tokens.extend(tokenize(&strip_html(&post.body)));
+        tokens.extend(tokenize(&post.author.name));
+        tokens.extend(tokenize(&post.flair.text));
+        add_comment_tokens(&mut tokens, comments);
+
+        Self {
+            doc: SearchDoc {
+                title: post.title.clone(),
+                url: post.permalink.clone(),
+                community: post.community.clone(),
+                author: post.author.name.clone(),
+                flair: post.flair.text.clone(),
+                created: post.created.clone(),
+            },
+            created_ts: post.created_ts,
+            tokens: tokens.into_iter().collect::<Vec<_>>().join(" "),
+        }
+    }
+}
+
+/// Inverted index over titles, selftext, comment bodies, authors and flair.
+#[derive(Default, Serialize)]
+pub struct SearchIndex {
+    pub docs: Vec<SearchDoc>,
+    /// Token to the (ascending) positions in `docs` of the posts containing it
+    pub terms: BTreeMap<String, Vec<u32>>,
+}
+
+impl SearchIndex {
+    /// Build an index over a set of posts. Documents are ordered newest first.
+    pub fn build<'a>(entries: impl IntoIterator<Item = &'a SearchEntry>) -> Self {
+        let mut entries: Vec<&SearchEntry> = entries.into_iter().collect();
+        entries.sort_by(|a, b| b.created_ts.cmp(&a.created_ts).then_with(|| a.doc.url.cmp(&b.doc.url)));
+
+        let mut index = Self::default();
+        for entry in entries {
+            index.add(entry);
+        }
+
+        index
+    }
+
+    /// Add a post to the index.
+    pub fn add(&mut self, entry: &SearchEntry) {
+        let doc = self.docs.len() as u32;
+        for token in entry.tokens.split(' ').filter(|token| !token.is_empty()) {
+            self.terms.entry(token.to_string()).or_default().push(doc);
+        }
+        self.docs.push(entry.doc.clone());
+    }
+
+    /// Serialize the index as a script defining `ARCHIVE_SEARCH_INDEX`.
+    pub fn to_script(&self) -> Result<String, Box<dyn Error>> {
+        Ok(format!("window.ARCHIVE_SEARCH_INDEX = {};\n", serde_json::to_string(self)?))
+    }
+}
+
+fn add_comment_tokens(tokens: &mut BTreeSet<String>, comments: &[Comment]) {
+    for comment in comments {
+        tokens.extend(tokenize(&strip_html(&comment.body)));
+        tokens.extend(tokenize(&comment.author.name));
+        add_comment_tokens(tokens, &comment.replies);
+    }
+}
+
+/// Remove tags and decode the common entities from rendered HTML.
+pub fn strip_html(html: &str) -> String {
+    HTML_TAG
+        .replace_all(html, " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Split text into lowercase search tokens. Must match `tokenize()` in
+/// `static/archiveSearch.js`.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    TOKEN_SEPARATOR
+        .split(text)
+        .filter(|token| (MIN_TOKEN_LEN..=MAX_TOKEN_LEN).contains(&token.chars().count()))
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<String> = tokenize("Hello, World! a rust_lang 2024 don't").collect();
+        assert_eq!(tokens, vec!["hello", "world", "rust_lang", "2024", "don"]);
+
+        // Combining marks stay part of their word, like in the browser
+        let tokens: Vec<String> = tokenize("Café cafe\u{301} हिन्दी ÜBER—straße").collect();
+        assert_eq!(tokens, vec!["café", "cafe\u{301}", "हिन्दी", "über", "straße"]);
+    }
+
+    #[test]
+    fn test_strip_html() {
+        assert_eq!(strip_html("<div class=\"md\"><p>Fish &amp; chips</p></div>").trim(), "Fish & chips");
+    }
+}
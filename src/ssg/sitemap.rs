@@ -0,0 +1,121 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Sitemaps and robots.txt for static archives hosted at a known base URL.
+
+use std::path::{Path, PathBuf};
+
+use url::Url;
+
+use crate::ssg::util::page_url;
+
+/// Name of the sitemap (or sitemap index), relative to the archive root
+pub const SITEMAP_FILE: &str = "sitemap.xml";
+
+/// Name of the robots.txt file, relative to the archive root
+pub const ROBOTS_FILE: &str = "robots.txt";
+
+/// Maximum number of URLs in one sitemap, as set by the sitemap protocol
+const SITEMAP_LIMIT: usize = 50_000;
+
+/// Sitemaps listing every page, keyed by their path relative to the archive
+/// root. Archives with more pages than fit into one sitemap get a sitemap
+/// index pointing at numbered sitemaps.
+pub fn sitemaps(base_url: &str, pages: &[PathBuf]) -> Vec<(PathBuf, String)> {
+    let urls: Vec<String> = pages.iter().map(|page| page_url(base_url, page)).collect();
+
+    if urls.len() <= SITEMAP_LIMIT {
+        return vec![(PathBuf::from(SITEMAP_FILE), urlset(&urls))];
+    }
+
+    let mut files = Vec::new();
+    let mut index = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for (i, chunk) in urls.chunks(SITEMAP_LIMIT).enumerate() {
+        let path = PathBuf::from(format!("sitemap-{}.xml", i + 1));
+        index.push_str(&format!("  <sitemap><loc>{}</loc></sitemap>\n", escape(&page_url(base_url, &path))));
+        files.push((path, urlset(chunk)));
+    }
+    index.push_str("</sitemapindex>\n");
+    files.insert(0, (PathBuf::from(SITEMAP_FILE), index));
+
+    files
+}
+
+fn urlset(urls: &[String]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for url in urls {
+        xml.push_str(&format!("  <url><loc>{}</loc></url>\n", escape(url)));
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// Rules for crawlers. Like the live server, author pages are kept out of
+/// search engines. Crawlers only read robots.txt at the root of the host, so
+/// when the archive is hosted below it the file has to be moved there.
+pub fn robots_txt(base_url: Option<&str>, disable_indexing: bool) -> String {
+    if disable_indexing {
+        return "User-agent: *\nDisallow: /\n".to_string();
+    }
+
+    // Rules are relative to the host, which may not be the archive root
+    let root = base_url.map(base_path).unwrap_or_default();
+    let mut robots = format!("User-agent: *\nDisallow: {root}/user/\n");
+    if let Some(base_url) = base_url {
+        robots.push_str(&format!("\nSitemap: {}\n", page_url(base_url, Path::new(SITEMAP_FILE))));
+    }
+
+    robots
+}
+
+/// Whether crawlers find robots.txt at the archive root, which is only the
+/// case when the archive is hosted at the root of the host
+pub fn robots_txt_found(base_url: Option<&str>) -> bool {
+    base_url.map_or(true, |url| base_path(url).is_empty())
+}
+
+/// Path of the base URL, without a trailing slash
+fn base_path(base_url: &str) -> String {
+    Url::parse(base_url).map(|url| url.path().trim_end_matches('/').to_string()).unwrap_or_default()
+}
+
+fn escape(text: &str) -> String {
+    text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sitemaps() {
+        let pages = vec![PathBuf::from("index.html"), PathBuf::from("r/rust/index.html")];
+        let files = sitemaps("https://archive.example", &pages);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].1.contains("<loc>https://archive.example/r/rust/index.html</loc>"));
+
+        let pages: Vec<PathBuf> = (0..SITEMAP_LIMIT + 1).map(|i| PathBuf::from(format!("{i}.html"))).collect();
+        let files = sitemaps("https://archive.example", &pages);
+        assert_eq!(files.len(), 3);
+        assert!(files[0].1.contains("<loc>https://archive.example/sitemap-2.xml</loc>"));
+        assert!(files[2].1.contains("<loc>https://archive.example/50000.html</loc>"));
+    }
+
+    #[test]
+    fn test_robots_txt() {
+        assert_eq!(
+            robots_txt(Some("https://example.com/archive"), false),
+            "User-agent: *\nDisallow: /archive/user/\n\nSitemap: https://example.com/archive/sitemap.xml\n"
+        );
+        assert_eq!(robots_txt(None, false), "User-agent: *\nDisallow: /user/\n");
+        assert_eq!(robots_txt(Some("https://example.com"), true), "User-agent: *\nDisallow: /\n");
+
+        assert!(robots_txt_found(None) && robots_txt_found(Some("https://example.com/")));
+        assert!(!robots_txt_found(Some("https://example.com/archive")));
+    }
+}
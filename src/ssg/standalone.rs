@@ -0,0 +1,213 @@
+#![forbid(unsafe_code)]
+#![allow(clippy::cmp_owned)]
+
+// Comments BDFR archived on their own, like those of a user, each in a file
+// of its own. They are added to the comment tree of their submission if it
+// was archived too, and otherwise built into a placeholder thread from the
+// file of the first of them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::bdfr::{is_comment_entry, CommentArchiveEntry};
+use crate::ssg::manifest::hash_file;
+use crate::ssg::template::ArchiveFormat;
+use crate::ssg::util::parallel_map;
+
+/// Comments of a submission that were archived on their own
+pub struct CommentGroup {
+    /// ID of the submission
+    pub submission: String,
+    /// Inputs of the comments, in order
+    pub files: Vec<PathBuf>,
+    /// Hash of every input of the comments
+    pub hash: String,
+    /// Whether the submission was archived too
+    pub archived: bool,
+}
+
+impl CommentGroup {
+    /// Read the comments, which is only needed when their thread is built
+    pub fn entries(&self, format: Option<ArchiveFormat>) -> Result<Vec<CommentArchiveEntry>, Box<dyn Error>> {
+        self.files
+            .iter()
+            .map(|path| Ok(serde_json::from_value(ArchiveFormat::read(path, format)?).map_err(|e| format!("{}: {e}", path.display()))?))
+            .collect()
+    }
+}
+
+/// What an input of a build with standalone comments holds
+pub enum StandaloneInput<'a> {
+    /// A submission, along with the comments archived on their own that
+    /// belong to it, if any
+    Post(Option<&'a CommentGroup>),
+    /// The first comment of a submission that was not archived, which the
+    /// placeholder thread of the comments is built from
+    Placeholder(&'a CommentGroup),
+    /// A comment built with its submission or placeholder
+    Merged,
+}
+
+/// An archive, as far as the index is concerned. Cached in the build
+/// manifest by the hash of the archive, so that unchanged archives are not
+/// read again to find the standalone comments.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Archive {
+    /// A submission, with its ID
+    Post(String),
+    /// A standalone comment, with the ID of its submission
+    Comment(String),
+}
+
+/// Standalone comments of a build, by the ID of their submission
+#[derive(Default)]
+pub struct StandaloneComments {
+    /// Inputs of the submissions with standalone comments, and their IDs
+    posts: HashMap<PathBuf, String>,
+    groups: BTreeMap<String, CommentGroup>,
+    /// Inputs of standalone comments, and the ID of their submission
+    comments: HashMap<PathBuf, String>,
+    /// Hashes of the inputs
+    hashes: HashMap<PathBuf, String>,
+    /// What the inputs hold, by their hash
+    archives: BTreeMap<String, Archive>,
+}
+
+impl StandaloneComments {
+    /// Find the standalone comments among BDFR archives. Only archives that
+    /// are not in `cached`, the index of the previous build, are read.
+    /// Archives that cannot be read are left for the build to report.
+    pub fn scan(paths: &[PathBuf], format: Option<ArchiveFormat>, cached: &BTreeMap<String, Archive>, workers: usize) -> Self {
+        let archives = parallel_map("Indexing comments", paths, workers, |path| {
+            let hash = hash_file(path).ok()?;
+            if let Some(archive) = cached.get(&hash) {
+                return Some((hash, archive.clone()));
+            }
+            let json = ArchiveFormat::read(path, format).ok()?;
+            let archive = if is_comment_entry(&json) {
+                Archive::Comment(serde_json::from_value::<CommentArchiveEntry>(json).ok()?.submission)
+            } else {
+                Archive::Post(json["id"].as_str()?.to_string())
+            };
+            Some((hash, archive))
+        });
+
+        let mut index = Self::default();
+        let mut posts = HashMap::new();
+        let mut hashers: BTreeMap<String, Sha256> = BTreeMap::new();
+        for (path, (hash, archive)) in paths.iter().zip(archives).filter_map(|(path, archive)| Some((path, archive?))) {
+            match &archive {
+                Archive::Post(id) => {
+                    posts.insert(path.clone(), id.clone());
+                }
+                Archive::Comment(id) => {
+                    hashers.entry(id.clone()).or_default().update(&hash);
+                    let group = index.groups.entry(id.clone()).or_insert_with(|| CommentGroup {
+                        submission: id.clone(),
+                        files: Vec::new(),
+                        hash: String::new(),
+                        archived: false,
+                    });
+                    group.files.push(path.clone());
+                    index.comments.insert(path.clone(), id.clone());
+                }
+            }
+            index.hashes.insert(path.clone(), hash.clone());
+            index.archives.insert(hash, archive);
+        }
+        for (id, hasher) in hashers {
+            if let Some(group) = index.groups.get_mut(&id) {
+                group.hash = format!("{:x}", hasher.finalize());
+            }
+        }
+
+        // Only submissions with standalone comments are of interest
+        index.posts = posts.into_iter().filter(|(_, id)| index.groups.contains_key(id)).collect();
+        for id in index.posts.values() {
+            if let Some(group) = index.groups.get_mut(id) {
+                group.archived = true;
+            }
+        }
+        index
+    }
+
+    /// What an input holds
+    pub fn input(&self, path: &Path) -> StandaloneInput<'_> {
+        if let Some(id) = self.posts.get(path) {
+            return StandaloneInput::Post(self.groups.get(id));
+        }
+        let Some(group) = self.comments.get(path).and_then(|id| self.groups.get(id)) else {
+            return StandaloneInput::Post(None);
+        };
+        if !group.archived && group.files.first().is_some_and(|first| first == path) {
+            StandaloneInput::Placeholder(group)
+        } else {
+            StandaloneInput::Merged
+        }
+    }
+
+    /// Hash of an input, as computed when indexing it
+    pub fn hash(&self, path: &Path) -> Option<&str> {
+        self.hashes.get(path).map(String::as_str)
+    }
+
+    /// What the inputs hold, by their hash, to be cached for the next build
+    pub fn archives(&self) -> &BTreeMap<String, Archive> {
+        &self.archives
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn test_standalone_comments() {
+        let comment = |id: &str, submission: &str, parent: &str| {
+            format!(
+                r#"{{"author": "ferris", "id": "{id}", "score": 1, "subreddit": "rust", "author_flair": null, "submission": "{submission}", "stickied": false, "body": "hi", "is_submitter": false, "distinguished": null, "created_utc": 1600000000.0, "parent_id": "{parent}", "replies": [], "submission_title": "Hello"}}"#
+            )
+        };
+        let dir = std::env::temp_dir().join(format!("ssg-standalone-test-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = ["a_post.json", "b_comment.json", "c_comment.json", "d_orphan.json", "e_orphan.json", "f_other.json"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        write(&paths[0], include_str!("../../tests/fixtures/bdfr/self_post.json")).unwrap();
+        write(&paths[1], comment("k1", "1fjx2k9", "t1_lnsk3vd")).unwrap();
+        write(&paths[2], comment("k2", "1fjx2k9", "t3_1fjx2k9")).unwrap();
+        write(&paths[3], comment("k3", "zzz999", "t3_zzz999")).unwrap();
+        write(&paths[4], comment("k4", "zzz999", "t1_k3")).unwrap();
+        write(&paths[5], include_str!("../../tests/fixtures/bdfr/link_post.json")).unwrap();
+
+        let index = StandaloneComments::scan(&paths, None, &BTreeMap::new(), 2);
+        let inputs: Vec<StandaloneInput> = paths.iter().map(|path| index.input(path)).collect();
+        let StandaloneInput::Placeholder(group) = &inputs[3] else { panic!("expected a placeholder") };
+        assert!(!group.archived);
+        let entries = group.entries(None).unwrap();
+
+        // Archives with a cached hash are not read again
+        let mut cached = index.archives().clone();
+        cached.insert(index.hash(&paths[5]).unwrap().to_string(), Archive::Comment("zzz999".to_string()));
+        let rescanned = StandaloneComments::scan(&paths, None, &cached, 2);
+        remove_dir_all(&dir).unwrap();
+
+        let StandaloneInput::Post(Some(group)) = &inputs[0] else { panic!("expected a post with comments") };
+        assert_eq!(group.files, paths[1..3]);
+        assert!(group.archived);
+        assert!(matches!(inputs[1], StandaloneInput::Merged) && matches!(inputs[2], StandaloneInput::Merged));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].id, "k4");
+        assert!(matches!(inputs[4], StandaloneInput::Merged));
+        assert!(matches!(inputs[5], StandaloneInput::Post(None)));
+
+        assert!(matches!(rescanned.input(&paths[0]), StandaloneInput::Post(Some(_))));
+        assert!(matches!(rescanned.input(&paths[5]), StandaloneInput::Merged));
+    }
+}
@@ -1,19 +1,40 @@
 #![forbid(unsafe_code)]
 #![allow(clippy::cmp_owned)]
 
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
 use std::fmt;
+use std::mem::take;
+use std::path::{Path, PathBuf};
 
-use crate::bdfr::SubmissionArchiveEntry;
-use crate::post::{comment_query, PostTemplate};
+use crate::bdfr::{xml_to_json, SubmissionArchiveEntry};
+use crate::post::{comment_query, parse_comments, PostTemplate};
+use crate::ssg::dump::DUMP_EXTENSIONS;
+use crate::ssg::util::{
+    archive_root, comment_dir_href, comment_page_href, page_url, subreddit_page_href, subreddit_page_path, user_page_href, user_page_path,
+};
 use crate::subreddit::SubredditTemplate;
-use crate::utils::{Post, Preferences, Subreddit};
+use crate::user::UserTemplate;
+use crate::utils::{parse_post, Comment, Post, Preferences, Subreddit, User};
 
 use clap::ValueEnum;
+use futures_lite::future::block_on;
+use log::{debug, warn};
+use rinja::Template;
 use serde_json::Value as JsonValue;
 
+/// A post and its comment tree
+pub type Thread = (Post, Vec<Comment>);
+
+/// Threads of an input, read one at a time
+pub type Threads<'a> = Box<dyn Iterator<Item = Result<Thread, Box<dyn Error>>> + 'a>;
+
 pub trait PostTemplater {
     fn template(&self) -> PostTemplate;
+
+    /// Build the original post and its comment tree
+    fn thread(&self) -> Result<(Post, Vec<Comment>), Box<dyn Error>>;
 }
 
 impl PostTemplater for SubmissionArchiveEntry {
@@ -28,6 +49,70 @@ impl PostTemplater for SubmissionArchiveEntry {
             comment_query(&self.url),
         )
     }
+
+    fn thread(&self) -> Result<(Post, Vec<Comment>), Box<dyn Error>> {
+        Ok((self.to_post()?, self.comments()))
+    }
+}
+
+/// A thread as returned by Reddit's `/comments/<id>.json` endpoint: a
+/// two-element array holding the post listing and the comment listing.
+pub struct RedditThread {
+    listing: JsonValue,
+}
+
+impl RedditThread {
+    /// Validate the shape of a raw Reddit API response.
+    pub fn new(listing: JsonValue) -> Result<Self, Box<dyn Error>> {
+        match listing.as_array() {
+            Some(parts) if parts.len() == 2 && parts[0]["data"]["children"][0].is_object() => Ok(Self { listing }),
+            Some(_) => Err("expected a listing of one post followed by its comments".into()),
+            None => Err("expected a JSON array".into()),
+        }
+    }
+
+    /// Parse the post with the same logic used by the live server.
+    pub fn to_post(&self) -> Post {
+        // `parse_post` is async, but does no I/O.
+        block_on(parse_post(&self.listing[0]["data"]["children"][0]))
+    }
+
+    /// Parse the comment tree with the same logic used by the live server.
+    pub fn comments(&self, post: &Post) -> Vec<Comment> {
+        parse_comments(
+            &self.listing[1],
+            &post.permalink,
+            &post.author.name,
+            "",
+            &HashSet::new(),
+            &Preferences::default(),
+        )
+    }
+}
+
+impl PostTemplater for RedditThread {
+    fn template(&self) -> PostTemplate {
+        let post = self.to_post();
+        let comments = self.comments(&post);
+        let url = post.permalink.clone();
+
+        PostTemplate::new(
+            post,
+            comments,
+            "new".to_string(),
+            Preferences::default(),
+            false,
+            url,
+            String::new(),
+        )
+    }
+
+    fn thread(&self) -> Result<(Post, Vec<Comment>), Box<dyn Error>> {
+        let post = self.to_post();
+        let comments = self.comments(&post);
+
+        Ok((post, comments))
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum)]
@@ -36,19 +121,98 @@ pub enum InputFormat {
     BDFRSelfPost,
     /// Inputs are JSON posts from Reddit's API.
     RedditJson,
+    /// Inputs are JSON arrays of posts written by `scraper`, without comments.
+    Scraper,
+    /// Inputs are Pushshift or Arctic Shift dumps of submissions and comments, optionally zstd-compressed.
+    Pushshift,
+    /// Inputs are Reddit data exports, the CSV files sent in answer to a GDPR request.
+    GdprExport,
 }
 
 impl InputFormat {
-    pub fn json_decode(&self, json: JsonValue) -> Result<impl PostTemplater, Box<dyn Error>> {
+    pub fn json_decode(&self, json: JsonValue) -> Result<Box<dyn PostTemplater>, Box<dyn Error>> {
         match self {
             InputFormat::BDFRSelfPost => {
-                Ok(serde_json::from_value::<SubmissionArchiveEntry>(json)?)
+                Ok(Box::new(serde_json::from_value::<SubmissionArchiveEntry>(json)?))
             },
             InputFormat::RedditJson => {
-                todo!()
+                Ok(Box::new(RedditThread::new(json)?))
             }
+            InputFormat::Scraper => Err("scraper files hold several posts, see threads()".into()),
+            InputFormat::Pushshift => Err("dumps are read line by line, see ssg::dump".into()),
+            InputFormat::GdprExport => Err("exports are CSV files, see ssg::gdpr".into()),
+        }
+    }
+
+    /// Whether an input file holds several posts rather than one
+    pub fn is_multi_post(&self) -> bool {
+        matches!(self, InputFormat::Scraper | InputFormat::Pushshift | InputFormat::GdprExport)
+    }
+
+    /// Extensions of input files
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            InputFormat::Pushshift => &DUMP_EXTENSIONS,
+            InputFormat::GdprExport => &["csv"],
+            InputFormat::BDFRSelfPost => &BDFR_EXTENSIONS,
+            _ => &["json"],
         }
     }
+
+    /// Posts of an input file and their comment trees
+    pub fn threads(&self, json: JsonValue) -> Result<Vec<Thread>, Box<dyn Error>> {
+        match self {
+            InputFormat::Scraper => Ok(serde_json::from_value::<Vec<Post>>(json)?.into_iter().map(|post| (post, Vec::new())).collect()),
+            _ => Ok(vec![self.json_decode(json)?.thread()?]),
+        }
+    }
+}
+
+/// Extensions of BDFR archives, in any format
+const BDFR_EXTENSIONS: [&str; 4] = ["json", "yaml", "yml", "xml"];
+
+/// Format BDFR's archiver wrote its files in, see its `--format` option
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum ArchiveFormat {
+    Json,
+    Yaml,
+    Xml,
+}
+
+impl ArchiveFormat {
+    /// Format of an archive, by its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "json" => Some(ArchiveFormat::Json),
+            "yaml" | "yml" => Some(ArchiveFormat::Yaml),
+            "xml" => Some(ArchiveFormat::Xml),
+            _ => None,
+        }
+    }
+
+    /// Extensions of archives in this format
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            ArchiveFormat::Json => &["json"],
+            ArchiveFormat::Yaml => &["yaml", "yml"],
+            ArchiveFormat::Xml => &["xml"],
+        }
+    }
+
+    /// Read an archive file, in `format` or else the one its extension tells
+    pub fn read(path: &Path, format: Option<Self>) -> Result<JsonValue, Box<dyn Error>> {
+        let format = format.or_else(|| Self::from_path(path)).unwrap_or(ArchiveFormat::Json);
+        format.decode(&std::fs::read_to_string(path)?)
+    }
+
+    /// Read an archive into the shape of BDFR's JSON archives
+    pub fn decode(&self, text: &str) -> Result<JsonValue, Box<dyn Error>> {
+        Ok(match self {
+            ArchiveFormat::Json => serde_json::from_str(text)?,
+            ArchiveFormat::Yaml => serde_yaml::from_str(text)?,
+            ArchiveFormat::Xml => xml_to_json(text)?,
+        })
+    }
 }
 
 impl fmt::Display for InputFormat {
@@ -56,43 +220,781 @@ impl fmt::Display for InputFormat {
         let s = match self {
             InputFormat::BDFRSelfPost => "bdfr-self-post",
             InputFormat::RedditJson => "reddit-json",
+            InputFormat::Scraper => "scraper",
+            InputFormat::Pushshift => "pushshift",
+            InputFormat::GdprExport => "gdpr-export",
         };
         write!(f, "{:?}", s)
     }
 }
 
-/// Generate a SubredditTemplate for a static archive.
+/// Index page listing every subreddit in a static archive.
+#[derive(Template)]
+#[template(path = "directory.html")]
+pub struct DirectoryTemplate {
+    pub title: String,
+    pub description: String,
+    /// Subreddit names and the number of archived posts in each
+    pub subs: Vec<(String, usize)>,
+    pub prefs: Preferences,
+    pub url: String,
+}
+
+/// Group posts by subreddit, newest first.
+pub fn group_by_community(posts: Vec<Post>) -> BTreeMap<String, Vec<Post>> {
+    let mut groups: BTreeMap<String, Vec<Post>> = BTreeMap::new();
+
+    for post in posts {
+        if post.community.is_empty() {
+            warn!("Post {} has no subreddit and will not be indexed", post.id);
+            continue;
+        }
+        groups.entry(post.community.clone()).or_default().push(post);
+    }
+
+    for posts in groups.values_mut() {
+        posts.sort_by(|a, b| b.created_ts.cmp(&a.created_ts).then_with(|| a.id.cmp(&b.id)));
+    }
+
+    groups
+}
+
+/// Period a top-scoring subreddit index covers. Archives are not updated
+/// live, so periods are counted back from the subreddit's newest post.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum)]
+pub enum Timeframe {
+    Month,
+    Year,
+    All,
+}
+
+impl Timeframe {
+    pub fn name(self) -> &'static str {
+        match self {
+            Timeframe::Month => "month",
+            Timeframe::Year => "year",
+            Timeframe::All => "all",
+        }
+    }
+
+    fn seconds(self) -> Option<u64> {
+        match self {
+            Timeframe::Month => Some(30 * 24 * 60 * 60),
+            Timeframe::Year => Some(365 * 24 * 60 * 60),
+            Timeframe::All => None,
+        }
+    }
+}
+
+/// Order of the posts in a subreddit index
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SortOrder {
+    New,
+    Top(Timeframe),
+    Comments,
+}
+
+impl SortOrder {
+    /// Every order subreddit indexes are generated in. Top posts of all time
+    /// are always included.
+    pub fn all(timeframes: &[Timeframe]) -> Vec<Self> {
+        let mut timeframes = timeframes.to_vec();
+        timeframes.push(Timeframe::All);
+        timeframes.sort();
+        timeframes.dedup();
+
+        let mut orders = vec![SortOrder::New];
+        orders.extend(timeframes.into_iter().map(SortOrder::Top));
+        orders.push(SortOrder::Comments);
+        orders
+    }
+
+    /// Sort and timeframe, as passed to Reddit
+    pub fn sort(self) -> (String, String) {
+        match self {
+            SortOrder::New => ("new".to_string(), String::new()),
+            SortOrder::Top(timeframe) => ("top".to_string(), timeframe.name().to_string()),
+            SortOrder::Comments => ("comments".to_string(), String::new()),
+        }
+    }
+
+    /// Directory of the index in this order, relative to the subreddit's
+    pub fn dir(self) -> String {
+        match self {
+            SortOrder::New => String::new(),
+            SortOrder::Top(Timeframe::All) => "top".to_string(),
+            SortOrder::Top(timeframe) => format!("top/{}", timeframe.name()),
+            SortOrder::Comments => "comments".to_string(),
+        }
+    }
+
+    /// Put posts (newest first) in this order, leaving out those outside of
+    /// its timeframe. Ties stay newest first.
+    pub fn apply(self, posts: &[Post]) -> Vec<Post> {
+        let mut posts = posts.to_vec();
+        match self {
+            SortOrder::New => {}
+            SortOrder::Top(timeframe) => {
+                if let (Some(seconds), Some(newest)) = (timeframe.seconds(), posts.first().map(|post| post.created_ts)) {
+                    posts.retain(|post| post.created_ts + seconds > newest);
+                }
+                posts.sort_by_key(|post| Reverse(count(&post.score)));
+            }
+            SortOrder::Comments => posts.sort_by_key(|post| Reverse(count(&post.comments))),
+        }
+        posts
+    }
+}
+
+/// Exact value of a number formatted by `format_num()`
+fn count(num: &(String, String)) -> i64 {
+    num.1.parse().unwrap_or_default()
+}
+
+/// Number of pages of a subreddit index. Empty subreddits still get one.
+pub fn subreddit_page_count(posts: usize, page_size: usize) -> usize {
+    posts.div_ceil(page_size.max(1)).max(1)
+}
+
+/// Generate the paginated SubredditTemplates for one subreddit of a static
+/// archive in every order, keyed by their path relative to the archive root.
 pub fn create_subreddit(
-    posts: Vec<Post>,
+    name: &str,
+    posts: &[Post],
+    page_size: usize,
+    orders: &[SortOrder],
+    prefs: &Preferences,
+) -> Vec<(PathBuf, SubredditTemplate)> {
+    // Links to the first page of every order. Top links to all time, with
+    // the other timeframes offered once it is selected.
+    let mut sort_links = Vec::new();
+    let mut timeframe_links = Vec::new();
+    for order in orders {
+        let (sort, timeframe) = order.sort();
+        let href = subreddit_page_href(name, &order.dir(), 1);
+        if let SortOrder::Top(t) = order {
+            timeframe_links.push((timeframe, href.clone()));
+            if *t != Timeframe::All {
+                continue;
+            }
+        }
+        sort_links.push((sort, href));
+    }
+    if timeframe_links.len() < 2 {
+        timeframe_links.clear();
+    }
+
+    let mut pages = Vec::new();
+    for order in orders {
+        let dir = order.dir();
+        let sorted = order.apply(posts);
+        let chunks: Vec<&[Post]> = sorted.chunks(page_size.max(1)).collect();
+        let page_count = subreddit_page_count(sorted.len(), page_size);
+
+        for page in 1..=page_count {
+            let page_posts = chunks.get(page - 1).map(|p| p.to_vec()).unwrap_or_default();
+            let path = subreddit_page_path(name, &dir, page);
+            let prev = if page > 1 { subreddit_page_href(name, &dir, page - 1) } else { String::new() };
+            let next = if page < page_count { subreddit_page_href(name, &dir, page + 1) } else { String::new() };
+
+            let tmpl = SubredditTemplate {
+                sub: Subreddit {
+                    name: name.to_string(),
+                    title: format!("r/{name}"),
+                    description: format!("{} archived {}", posts.len(), if posts.len() == 1 { "post" } else { "posts" }),
+                    ..Subreddit::default()
+                },
+                url: page_url(&prefs.archive_base_url, &path),
+                no_posts: page_posts.is_empty(),
+                posts: page_posts,
+                sort: order.sort(),
+                ends: (prev, next),
+                prefs: Preferences {
+                    archive_root: archive_root(&path),
+                    ..prefs.clone()
+                },
+                redirect_url: subreddit_page_href(name, &dir, page),
+                is_filtered: false,
+                all_posts_filtered: false,
+                all_posts_hidden_nsfw: false,
+                sort_links: sort_links.clone(),
+                timeframe_links: timeframe_links.clone(),
+            };
+
+            pages.push((path, tmpl));
+        }
+    }
+
+    pages
+}
+
+/// Generate the top-level page of a static archive, listing its subreddits.
+pub fn create_directory(
+    groups: &BTreeMap<String, Vec<Post>>,
     title: &str,
     description: &str,
-    prefs: Preferences,
-) -> Result<SubredditTemplate, Box<dyn Error>> {
-    println!("Creating subreddit template with {} posts", posts.len());
-
-    let no_posts = posts.is_empty();
-
-    Ok(SubredditTemplate {
-        sub: Subreddit {
-            name: "redlib".to_string(),
-            title: title.to_string(),
-            description: description.to_string(),
-            members: (String::new(), String::new()),
-            info: String::new(),
-            icon: String::new(),
-            active: (String::new(), String::new()),
-            wiki: false,
-            nsfw: false,
+    prefs: &Preferences,
+) -> DirectoryTemplate {
+    debug!("Creating directory template with {} subreddits", groups.len());
+
+    DirectoryTemplate {
+        title: title.to_string(),
+        description: description.to_string(),
+        subs: groups.iter().map(|(name, posts)| (name.clone(), posts.len())).collect(),
+        prefs: Preferences {
+            archive_root: archive_root(Path::new("index.html")),
+            ..prefs.clone()
         },
-        url: "example.com".to_string(),
-        posts,
-        sort: (String::new(), String::new()),
-        ends: (String::new(), String::new()),
-        prefs,
-        redirect_url: "redirect.example.com".to_string(),
-        is_filtered: false,
-        all_posts_filtered: false,
-        all_posts_hidden_nsfw: false,
-        no_posts,
-    })
-}
\ No newline at end of file
+        url: page_url(&prefs.archive_base_url, Path::new("index.html")),
+    }
+}
+
+/// Comments of a thread, as listed on their authors' pages, given the page of
+/// its post. Comments without a timestamp are dated by their post.
+pub fn comment_items(post: &Post, page: &Path, comments: &[Comment]) -> Vec<Post> {
+    let mut items = Vec::new();
+
+    for comment in comments.iter().filter(|comment| comment.kind == "t1") {
+        items.push(Post {
+            id: comment.id.clone(),
+            title: String::new(),
+            body: comment.body.clone(),
+            author: comment.author.clone(),
+            link_title: post.title.clone(),
+            permalink: comment_page_href(page, &comment.id),
+            score: comment.score.clone(),
+            rel_time: comment.rel_time.clone(),
+            created: comment.created.clone(),
+            created_ts: if comment.created_ts > 0 { comment.created_ts } else { post.created_ts },
+            gallery: Vec::new(),
+            poll: None,
+            ..post.clone()
+        });
+        items.extend(comment_items(post, page, &comment.replies));
+    }
+
+    items
+}
+
+/// Name Reddit gives the author of a post or comment whose account is gone
+const DELETED_AUTHOR: &str = "[deleted]";
+
+/// Whether an author gets a page of their own. Deleted accounts do not, as
+/// their posts have nothing in common.
+pub fn has_author_page(name: &str) -> bool {
+    !name.is_empty() && name != DELETED_AUTHOR
+}
+
+/// Group posts and comments by author, newest first.
+pub fn group_by_author(items: Vec<Post>) -> BTreeMap<String, Vec<Post>> {
+    let mut groups: BTreeMap<String, Vec<Post>> = BTreeMap::new();
+
+    for item in items {
+        if !has_author_page(&item.author.name) {
+            continue;
+        }
+        groups.entry(item.author.name.clone()).or_default().push(item);
+    }
+
+    // Stable, so comments stay in thread order
+    for items in groups.values_mut() {
+        items.sort_by_key(|item| Reverse(item.created_ts));
+    }
+
+    groups
+}
+
+/// Generate the paginated pages of an author of a static archive, listing
+/// their archived posts and comments, keyed by their path relative to the
+/// archive root.
+pub fn create_user(name: &str, items: &[Post], page_size: usize, prefs: &Preferences) -> Vec<(PathBuf, UserTemplate)> {
+    let posts = items.iter().filter(|item| !item.title.is_empty()).count();
+    let comments = items.len() - posts;
+    let user = || User {
+        name: name.to_string(),
+        title: format!("u/{name}"),
+        description: format!(
+            "{posts} archived {} and {comments} {}",
+            if posts == 1 { "post" } else { "posts" },
+            if comments == 1 { "comment" } else { "comments" },
+        ),
+        ..User::default()
+    };
+
+    let chunks: Vec<&[Post]> = items.chunks(page_size.max(1)).collect();
+    let page_count = subreddit_page_count(items.len(), page_size);
+    let mut pages = Vec::new();
+
+    for page in 1..=page_count {
+        let page_items = chunks.get(page - 1).map(|p| p.to_vec()).unwrap_or_default();
+        let path = user_page_path(name, page);
+        let prev = if page > 1 { user_page_href(name, page - 1) } else { String::new() };
+        let next = if page < page_count { user_page_href(name, page + 1) } else { String::new() };
+
+        let tmpl = UserTemplate {
+            user: user(),
+            no_posts: page_items.is_empty(),
+            posts: page_items,
+            sort: ("new".to_string(), String::new()),
+            ends: (prev, next),
+            listing: "overview".to_string(),
+            prefs: Preferences {
+                archive_root: archive_root(&path),
+                ..prefs.clone()
+            },
+            url: page_url(&prefs.archive_base_url, &path),
+            redirect_url: user_page_href(name, page),
+            is_filtered: false,
+            all_posts_filtered: false,
+            all_posts_hidden_nsfw: false,
+        };
+
+        pages.push((path, tmpl));
+    }
+
+    pages
+}
+
+/// Put comment trees in one of the orders of `config::COMMENT_SORTS`. Inputs
+/// are in Reddit's default order, so "confidence" keeps them as they are.
+/// Links to more comments stay last.
+pub fn sort_comments(comments: &mut [Comment], sort: &str) {
+    match sort {
+        "top" => comments.sort_by_key(|comment| (comment.kind == "more", Reverse(count(&comment.score)))),
+        "new" => comments.sort_by_key(|comment| (comment.kind == "more", Reverse(comment.created_ts))),
+        "old" => comments.sort_by_key(|comment| (comment.kind == "more", comment.created_ts)),
+        _ => return,
+    }
+    for comment in comments {
+        sort_comments(&mut comment.replies, sort);
+    }
+}
+
+/// Use the preferences of the page a comment tree is shown on.
+pub fn set_comment_prefs(comments: &mut [Comment], prefs: &Preferences) {
+    for comment in comments {
+        comment.prefs = prefs.clone();
+        set_comment_prefs(&mut comment.replies, prefs);
+    }
+}
+
+/// Link comments to their permalink pages, see `comment_threads()`. `page` is
+/// the page of their post.
+pub fn set_comment_links(comments: &mut [Comment], page: &Path) {
+    for comment in comments {
+        comment.post_link = comment_dir_href(page);
+        set_comment_links(&mut comment.replies, page);
+    }
+}
+
+/// Levels of replies shown under the comment of a permalink page. Deeper
+/// replies are linked to, so that pages stay small in long threads.
+const COMMENT_PAGE_DEPTH: usize = 3;
+
+/// Threads of the comments' permalink pages, by comment ID: each comment is
+/// highlighted with its replies up to `COMMENT_PAGE_DEPTH`, inside the chain
+/// of its parent comments.
+pub fn comment_threads(comments: &mut [Comment]) -> Vec<(String, Comment)> {
+    let mut threads = Vec::new();
+    collect_threads(comments, &mut Vec::new(), &mut threads);
+    threads
+}
+
+/// `parents` are copies of the enclosing comments without their replies.
+fn collect_threads(comments: &mut [Comment], parents: &mut Vec<Comment>, threads: &mut Vec<(String, Comment)>) {
+    for comment in comments.iter_mut().filter(|comment| comment.kind == "t1") {
+        let mut thread = Comment {
+            highlighted: true,
+            ..capped(comment, COMMENT_PAGE_DEPTH)
+        };
+        for parent in parents.iter().rev() {
+            thread = Comment {
+                replies: vec![thread],
+                collapsed: false,
+                ..parent.clone()
+            };
+        }
+        threads.push((comment.id.clone(), thread));
+
+        let replies = take(&mut comment.replies);
+        parents.push(comment.clone());
+        comment.replies = replies;
+        collect_threads(&mut comment.replies, parents, threads);
+        parents.pop();
+    }
+}
+
+/// Copy of a comment with `depth` levels of its replies. Deeper replies are
+/// replaced by a link to the permalink page of their parent.
+fn capped(comment: &mut Comment, depth: usize) -> Comment {
+    let mut replies = take(&mut comment.replies);
+    let mut copy = comment.clone();
+
+    if depth > 0 {
+        copy.replies = replies
+            .iter_mut()
+            .map(|reply| if reply.kind == "t1" { capped(reply, depth - 1) } else { reply.clone() })
+            .collect();
+    } else if replies.iter().any(|reply| reply.kind == "t1") {
+        copy.replies = vec![Comment {
+            id: String::new(),
+            kind: "more".to_string(),
+            parent_id: comment.id.clone(),
+            parent_kind: "t1".to_string(),
+            more_count: count_comments(&replies) as i64,
+            ..copy.clone()
+        }];
+    }
+
+    comment.replies = replies;
+    copy
+}
+
+/// Number of comments in comment trees, including those not archived
+fn count_comments(comments: &[Comment]) -> usize {
+    comments
+        .iter()
+        .map(|comment| if comment.kind == "more" { comment.more_count.max(0) as usize } else { 1 + count_comments(&comment.replies) })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_reddit_thread() {
+        let listing = json!([
+            {"kind": "Listing", "data": {"children": [{"kind": "t3", "data": {
+                "id": "abc123",
+                "title": "Hello archive",
+                "subreddit": "rust",
+                "author": "ferris",
+                "permalink": "/r/rust/comments/abc123/hello_archive/",
+                "created_utc": 1600000000.0,
+                "score": 42,
+                "upvote_ratio": 0.93,
+                "is_self": true,
+                "num_comments": 2,
+                "num_duplicates": 1
+            }}]}},
+            {"kind": "Listing", "data": {"children": [{"kind": "t1", "data": {
+                "id": "c1",
+                "author": "bob",
+                "body_html": "<p>hi</p>",
+                "created_utc": 1600000100.0,
+                "score": 3,
+                "parent_id": "t3_abc123",
+                "replies": {"kind": "Listing", "data": {"children": [{"kind": "t1", "data": {
+                    "id": "c2",
+                    "author": "ferris",
+                    "body_html": "<p>yo</p>",
+                    "created_utc": 1600000200.0,
+                    "score": 1,
+                    "parent_id": "t1_c1",
+                    "replies": ""
+                }}]}}
+            }}]}}
+        ]);
+
+        let thread = InputFormat::RedditJson.json_decode(listing).unwrap();
+        let (post, comments) = thread.thread().unwrap();
+        assert_eq!(post.id, "abc123");
+        assert_eq!(post.community, "rust");
+        assert_eq!(post.post_type, "self");
+        assert_eq!(post.upvote_ratio, 93);
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].post_link, post.permalink);
+        assert_eq!(comments[0].replies[0].author.name, "ferris");
+        assert_eq!(comments[0].replies[0].parent_kind, "t1");
+
+        // Duplicates are only linked to when served live
+        let duplicates = "/r/rust/duplicates/abc123";
+        assert!(thread.template().render().unwrap().contains(duplicates));
+        let prefs = Preferences {
+            archive_root: "./".to_string(),
+            ..Preferences::default()
+        };
+        let page = PostTemplate::new(post, comments, "new".to_string(), prefs, false, String::new(), String::new());
+        assert!(!page.render().unwrap().contains(duplicates));
+
+        assert!(InputFormat::RedditJson.json_decode(json!({"kind": "Listing"})).is_err());
+        assert!(InputFormat::RedditJson.json_decode(json!([{}, {}])).is_err());
+    }
+
+    #[test]
+    fn test_scraper_threads() {
+        let posts = [post("a", "rust", 1.0), post("b", "rust", 2.0)];
+        let json = serde_json::to_value(posts.iter().collect::<Vec<&Post>>()).unwrap();
+
+        let threads = InputFormat::Scraper.threads(json).unwrap();
+        assert_eq!(threads.iter().map(|(post, _)| post.id.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert!(threads.iter().all(|(_, comments)| comments.is_empty()));
+        assert!(InputFormat::Scraper.is_multi_post() && !InputFormat::BDFRSelfPost.is_multi_post());
+        assert!(InputFormat::Scraper.threads(json!({"id": "a"})).is_err());
+    }
+
+    #[test]
+    fn test_archive_formats() {
+        let json: JsonValue = serde_json::from_str(include_str!("../../tests/fixtures/bdfr/self_post.json")).unwrap();
+        let yaml = ArchiveFormat::Yaml.decode(include_str!("../../tests/fixtures/bdfr/self_post.yaml")).unwrap();
+        let xml = ArchiveFormat::Xml.decode(include_str!("../../tests/fixtures/bdfr/self_post.xml")).unwrap();
+        assert_eq!(yaml, json);
+        assert_eq!(xml, json);
+
+        let (post, comments) = InputFormat::BDFRSelfPost.json_decode(xml).unwrap().thread().unwrap();
+        assert_eq!(post.id, "1fjx2k9");
+        assert_eq!(comments[1].replies[0].id, "lnt0c2e");
+
+        assert_eq!(ArchiveFormat::from_path(Path::new("rust/abc.YML")), Some(ArchiveFormat::Yaml));
+        assert_eq!(ArchiveFormat::from_path(Path::new("rust/abc.txt")), None);
+        assert!(ArchiveFormat::Xml.decode("<root><id>a</id>").is_err());
+    }
+
+    fn post(id: &str, community: &str, created_utc: f64) -> Post {
+        block_on(parse_post(&json!({"data": {
+            "id": id,
+            "subreddit": community,
+            "created_utc": created_utc,
+        }})))
+    }
+
+    #[test]
+    fn test_create_subreddit() {
+        let posts = (0..5).map(|i| post(&format!("p{i}"), "rust", 1600000000.0 + f64::from(i))).collect();
+        let groups = group_by_community(posts);
+        let pages = create_subreddit("rust", &groups["rust"], 2, &[SortOrder::New], &Preferences::default());
+
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].0, PathBuf::from("r/rust/index.html"));
+        assert_eq!(pages[0].1.posts[0].id, "p4");
+        assert_eq!(pages[0].1.ends, (String::new(), "r/rust/2.html".to_string()));
+        assert_eq!(pages[1].1.ends, ("r/rust/index.html".to_string(), "r/rust/3.html".to_string()));
+        assert_eq!(pages[2].1.ends, ("r/rust/2.html".to_string(), String::new()));
+        assert_eq!(pages[2].1.posts.len(), 1);
+        assert_eq!(pages[2].1.prefs.archive_root, "../../");
+    }
+
+    #[test]
+    fn test_sort_orders() {
+        let posts: Vec<Post> = [(1, 10, 0), (2, 30, 5), (3, 20, 9)]
+            .iter()
+            .map(|(days, score, comments)| {
+                block_on(parse_post(&json!({"data": {
+                    "id": format!("p{days}"),
+                    "subreddit": "rust",
+                    "created_utc": 1600000000.0 + f64::from(days * 24 * 60 * 60),
+                    "score": score,
+                    "num_comments": comments,
+                }})))
+            })
+            .collect();
+        let posts = &group_by_community(posts)["rust"];
+        let ids = |order: SortOrder| order.apply(posts).iter().map(|p| p.id.clone()).collect::<Vec<_>>();
+
+        assert_eq!(ids(SortOrder::New), vec!["p3", "p2", "p1"]);
+        assert_eq!(ids(SortOrder::Top(Timeframe::All)), vec!["p2", "p3", "p1"]);
+        assert_eq!(ids(SortOrder::Comments), vec!["p3", "p2", "p1"]);
+
+        let orders = SortOrder::all(&[Timeframe::Year]);
+        assert_eq!(orders, vec![SortOrder::New, SortOrder::Top(Timeframe::Year), SortOrder::Top(Timeframe::All), SortOrder::Comments]);
+
+        let pages = create_subreddit("rust", posts, 25, &orders, &Preferences::default());
+        let paths: Vec<&Path> = pages.iter().map(|(path, _)| path.as_path()).collect();
+        assert_eq!(paths, vec![
+            Path::new("r/rust/index.html"),
+            Path::new("r/rust/top/year/index.html"),
+            Path::new("r/rust/top/index.html"),
+            Path::new("r/rust/comments/index.html"),
+        ]);
+        assert_eq!(pages[1].1.sort, ("top".to_string(), "year".to_string()));
+        assert_eq!(pages[1].1.sort_links[1], ("top".to_string(), "r/rust/top/index.html".to_string()));
+        assert_eq!(pages[1].1.timeframe_links[0], ("year".to_string(), "r/rust/top/year/index.html".to_string()));
+        assert_eq!(pages[1].1.prefs.archive_root, "../../../../");
+    }
+
+    #[test]
+    fn test_create_user() {
+        let (post, comments) = InputFormat::RedditJson.json_decode(json!([
+            {"data": {"children": [{"kind": "t3", "data": {
+                "id": "abc123",
+                "title": "Hello archive",
+                "subreddit": "rust",
+                "author": "ferris",
+                "created_utc": 1600000000.0
+            }}]}},
+            {"data": {"children": [{"kind": "t1", "data": {
+                "id": "c1",
+                "author": "bob",
+                "body_html": "<p>hi</p>",
+                "parent_id": "t3_abc123",
+                "replies": {"data": {"children": [{"kind": "t1", "data": {
+                    "id": "c2",
+                    "author": "ferris",
+                    "body_html": "<p>yo</p>",
+                    "parent_id": "t1_c1",
+                    "replies": ""
+                }}]}}
+            }}]}}
+        ]))
+        .unwrap()
+        .thread()
+        .unwrap();
+
+        let mut items = vec![post.clone()];
+        items.extend(comment_items(&post, Path::new("rust/hello.html"), &comments));
+        let groups = group_by_author(items);
+        assert_eq!(groups.keys().collect::<Vec<_>>(), vec!["bob", "ferris"]);
+        assert_eq!(groups["ferris"][1].link_title, "Hello archive");
+        assert!(groups["ferris"][1].title.is_empty());
+        assert_eq!(groups["ferris"][1].permalink, "comments/rust/hello/c2.html");
+
+        let pages = create_user("ferris", &groups["ferris"], 25, &Preferences::default());
+        let (path, user) = &pages[0];
+        assert_eq!(pages.len(), 1);
+        assert_eq!(*path, PathBuf::from("user/ferris.html"));
+        assert_eq!(user.user.description, "1 archived post and 1 comment");
+        assert_eq!(user.prefs.archive_root, "../");
+
+        let pages = create_user("ferris", &groups["ferris"], 1, &Preferences::default());
+        assert_eq!(pages[1].0, PathBuf::from("user/ferris/2.html"));
+        assert_eq!(pages[0].1.ends, (String::new(), "user/ferris/2.html".to_string()));
+        assert_eq!(pages[1].1.ends, ("user/ferris.html".to_string(), String::new()));
+        assert_eq!(pages[1].1.user.description, "1 archived post and 1 comment");
+        assert_eq!(pages[1].1.prefs.archive_root, "../../");
+
+        // Deleted accounts have no page
+        let mut deleted = post.clone();
+        deleted.author.name = "[deleted]".to_string();
+        assert!(group_by_author(vec![deleted]).is_empty());
+    }
+
+    #[test]
+    fn test_sort_comments() {
+        let comment = |id: &str, score: i64, created_utc: f64| json!({"kind": "t1", "data": {
+            "id": id,
+            "score": score,
+            "created_utc": created_utc,
+            "parent_id": "t3_abc123",
+            "replies": ""
+        }});
+        let (_, mut comments) = InputFormat::RedditJson
+            .json_decode(json!([
+                {"data": {"children": [{"kind": "t3", "data": {"id": "abc123"}}]}},
+                {"data": {"children": [comment("a", 1, 1600000300.0), comment("b", 5, 1600000100.0), comment("c", 3, 1600000200.0)]}}
+            ]))
+            .unwrap()
+            .thread()
+            .unwrap();
+        let ids = |comments: &[Comment]| comments.iter().map(|c| c.id.clone()).collect::<Vec<_>>();
+
+        sort_comments(&mut comments, "confidence");
+        assert_eq!(ids(&comments), vec!["a", "b", "c"]);
+        sort_comments(&mut comments, "top");
+        assert_eq!(ids(&comments), vec!["b", "c", "a"]);
+        sort_comments(&mut comments, "old");
+        assert_eq!(ids(&comments), vec!["b", "c", "a"]);
+        sort_comments(&mut comments, "new");
+        assert_eq!(ids(&comments), vec!["a", "c", "b"]);
+    }
+
+    #[test]
+    fn test_comment_threads() {
+        let comment = |id: &str, parent: &str, replies: JsonValue| json!({"kind": "t1", "data": {
+            "id": id,
+            "parent_id": parent,
+            "replies": replies
+        }});
+        let (_, mut comments) = InputFormat::RedditJson
+            .json_decode(json!([
+                {"data": {"children": [{"kind": "t3", "data": {"id": "abc123"}}]}},
+                {"data": {"children": [
+                    comment("a", "t3_abc123", json!({"data": {"children": [
+                        comment("b", "t1_a", json!({"data": {"children": [
+                            comment("c", "t1_b", json!({"data": {"children": [
+                                comment("e", "t1_c", json!({"data": {"children": [comment("f", "t1_e", json!(""))]}}))
+                            ]}}))
+                        ]}}))
+                    ]}})),
+                    comment("d", "t3_abc123", json!("")),
+                ]}}
+            ]))
+            .unwrap()
+            .thread()
+            .unwrap();
+        set_comment_links(&mut comments, Path::new("rust/hello.html"));
+        assert_eq!(comments[0].replies[0].post_link, "comments/rust/hello/");
+        comments[0].collapsed = true;
+
+        let threads = comment_threads(&mut comments);
+        assert_eq!(threads.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), vec!["a", "b", "c", "e", "f", "d"]);
+
+        // The parents of b, b itself and its replies
+        let (_, thread) = &threads[1];
+        assert_eq!(thread.id, "a");
+        assert!(!thread.highlighted && !thread.collapsed);
+        assert_eq!(thread.replies.len(), 1);
+        assert!(thread.replies[0].highlighted);
+        assert_eq!(thread.replies[0].replies[0].id, "c");
+        assert!(!thread.replies[0].replies[0].highlighted);
+
+        // Replies deeper than COMMENT_PAGE_DEPTH link to the page of their parent
+        let (_, thread) = &threads[0];
+        let e = &thread.replies[0].replies[0].replies[0];
+        assert_eq!(e.id, "e");
+        assert_eq!(e.replies.len(), 1);
+        assert_eq!((e.replies[0].kind.as_str(), e.replies[0].parent_id.as_str(), e.replies[0].more_count), ("more", "e", 1));
+
+        // The full tree is left as it was
+        assert_eq!(comments[0].replies[0].replies[0].id, "c");
+        assert!(comments.iter().all(|comment| !comment.highlighted));
+    }
+
+    #[test]
+    fn test_group_by_community() {
+        let groups = group_by_community(vec![post("a", "rust", 1.0), post("b", "golang", 2.0), post("c", "", 3.0), post("d", "rust", 4.0)]);
+
+        assert_eq!(groups.keys().collect::<Vec<_>>(), vec!["golang", "rust"]);
+        assert_eq!(groups["rust"].iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), vec!["d", "a"]);
+    }
+
+    #[test]
+    fn test_has_author_page() {
+        assert!(has_author_page("ferris"));
+        assert!(!has_author_page(""));
+        assert!(!has_author_page("[deleted]"));
+
+        // Authors without a page are not linked to from archived pages
+        let comment = |id: &str, author: &str| json!({"kind": "t1", "data": {
+            "id": id,
+            "author": author,
+            "body_html": "<p>hi</p>",
+            "created_utc": 1600000100.0,
+            "parent_id": "t3_abc123",
+            "replies": ""
+        }});
+        let listing = json!([
+            {"kind": "Listing", "data": {"children": [{"kind": "t3", "data": {
+                "id": "abc123",
+                "title": "Hello archive",
+                "subreddit": "rust",
+                "author": "",
+                "permalink": "/r/rust/comments/abc123/hello_archive/",
+                "created_utc": 1600000000.0,
+                "is_self": true
+            }}]}},
+            {"kind": "Listing", "data": {"children": [comment("c1", "[deleted]"), comment("c2", "ferris")]}}
+        ]);
+        let (post, mut comments) = InputFormat::RedditJson.json_decode(listing).unwrap().thread().unwrap();
+        let prefs = Preferences {
+            archive_root: "../".to_string(),
+            ..Preferences::default()
+        };
+        set_comment_prefs(&mut comments, &prefs);
+        let html = PostTemplate::new(post, comments, "new".to_string(), prefs, false, String::new(), String::new()).render().unwrap();
+        assert!(html.contains("u/[unknown]") && html.contains("u/[deleted]"));
+        assert!(!html.contains("user/.html") && !html.contains("user/[deleted]"));
+        assert!(html.contains("user/ferris.html"));
+    }
+}
@@ -1,52 +1,351 @@
 #![forbid(unsafe_code)]
 #![allow(clippy::cmp_owned)]
 
-use std::error::Error;
-use std::fs::{copy, create_dir_all, read_dir};
+use std::collections::HashSet;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::io;
-
-/// Resolve output path for a file being processed
-pub fn output_path(
-    input_path: &PathBuf,
-    out_dir: &PathBuf,
-    extension: String,
-) -> Result<PathBuf, Box<dyn Error>> {
-    //println!("Resolving output path for {}", input_path.display());
-
-    Ok(out_dir
-        .join(input_path.file_stem().expect("invalid filename"))
-        .with_extension(extension))
-}
-
-/// Copy directory recursively
-pub fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
-    // Check if source directory exists before trying to copy
-    if !src.as_ref().exists() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            format!("Source directory does not exist: {}", src.as_ref().display())
-        ));
-    }
-
-    // Create destination directory and all parent directories
-    create_dir_all(&dst)?;
-
-    //println!("Copying {} to {}", src.as_ref().display(), dst.as_ref().display());
-    for entry in read_dir(src)? {
-        //println!("Copying entry: {:?}", entry);
-        if entry.is_err() {
-            eprintln!("Error reading entry: {:?}", entry);
-            continue;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+/// Directories of generated pages, relative to the archive root. Post pages
+/// must stay out of them, they are cleared when rebuilt.
+const GENERATED_DIRS: [&str; 5] = ["r", "user", "media", "api", COMMENTS_DIR];
+
+/// Directory of the comment permalink pages, relative to the archive root
+const COMMENTS_DIR: &str = "comments";
+
+/// Generated pages at the archive root
+const GENERATED_PAGES: [&str; 2] = ["index.html", "search.html"];
+
+/// Characters percent-encoded in a component of a page's URL, besides
+/// non-ASCII ones
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+/// Page of an input file, relative to the archive root: its place in the
+/// source tree, relative to the source directory.
+pub fn mirror_path(input: &Path) -> PathBuf {
+    input.with_extension("html")
+}
+
+/// Page of a post of an input file that holds several, given the page the
+/// input would have on its own: a directory of pages named by post ID, see
+/// `PostNames`.
+pub fn multi_post_path(page: &Path, name: &str) -> PathBuf {
+    page.with_extension("").join(format!("{name}.html"))
+}
+
+/// Names of the pages of the posts of an input file that holds several, in
+/// the order the posts are read. Like in `output_paths()`, a post that would
+/// overwrite the page of an earlier one, also on case-insensitive file
+/// systems, gets a `-<n>` suffix. `output_paths()` gives every input a
+/// directory that no other input writes to, so posts of different inputs
+/// never clash.
+#[derive(Default)]
+pub struct PostNames {
+    taken: HashSet<String>,
+}
+
+impl PostNames {
+    /// Name of the page of the next post
+    pub fn name(&mut self, id: &str) -> String {
+        let mut name = id.to_string();
+        let mut n = 1;
+        while !self.taken.insert(name.to_lowercase()) {
+            n += 1;
+            name = format!("{id}-{n}");
+        }
+        name
+    }
+}
+
+/// Pages of input files (relative to the source directory), in the same
+/// order. Inputs that would overwrite a generated page or another input's
+/// page, also on case-insensitive file systems, are renamed: a clashing top
+/// directory gets a `_` prefix and a clashing name a `-<n>` suffix. Pages at
+/// the root are not named after a generated directory either, which the
+/// posts of an input that holds several would go to. If `multi_post`, the
+/// inputs are of such a format and a page whose directory (see
+/// `multi_post_path()`) holds other inputs is renamed as well. As long as the
+/// set of inputs stays the same, so do their pages.
+pub fn output_paths(inputs: &[PathBuf], static_path: &str, multi_post: bool) -> Vec<PathBuf> {
+    let generated_dir = |dir: &str| GENERATED_DIRS.contains(&dir) || dir == static_path.to_lowercase();
+    let reserved = |path: &Path| {
+        let mut components = path.components().map(|c| c.as_os_str().to_string_lossy().to_lowercase());
+        match (components.next(), components.next()) {
+            (Some(first), None) => GENERATED_PAGES.contains(&first.as_str()) || generated_dir(first.trim_end_matches(".html")),
+            (Some(first), Some(_)) => generated_dir(&first),
+            _ => false,
+        }
+    };
+    let key = |path: &Path| path.to_string_lossy().to_lowercase();
+    // Directories holding inputs, which the posts of another input must not
+    // be written to
+    let input_dirs: HashSet<String> = if multi_post {
+        inputs.iter().flat_map(|input| input.ancestors().skip(1)).map(key).collect()
+    } else {
+        HashSet::new()
+    };
+    let occupied = |path: &Path| input_dirs.contains(&key(&path.with_extension("")));
+
+    let mut taken = HashSet::new();
+    inputs
+        .iter()
+        .map(|input| {
+            let mut path = mirror_path(input);
+            if reserved(&path) && path.components().count() > 1 {
+                let mut components = path.components();
+                let first = components.next().unwrap().as_os_str().to_string_lossy();
+                path = Path::new(&format!("_{first}")).join(components.as_path());
+            }
+
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let mut n = 1;
+            while reserved(&path) || taken.contains(&key(&path)) || occupied(&path) {
+                n += 1;
+                path.set_file_name(format!("{stem}-{n}.html"));
+            }
+
+            taken.insert(key(&path));
+            path
+        })
+        .collect()
+}
+
+/// Relative path from a page to the root of the archive, used as the page's
+/// `<base href>` so that links resolve the same way at every depth.
+pub fn archive_root(page: &Path) -> String {
+    match page.components().count() {
+        0 | 1 => "./".to_string(),
+        n => "../".repeat(n - 1),
+    }
+}
+
+/// URL of a page, relative to the archive root. Absolute if the archive has a
+/// base URL, which must not end with a slash. Each component of the path is
+/// percent-encoded, as input files can be named anything.
+pub fn page_url(base_url: &str, page: &Path) -> String {
+    let href = page
+        .components()
+        .map(|component| utf8_percent_encode(&component.as_os_str().to_string_lossy(), PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    archive_url(base_url, &href)
+}
+
+/// URL of a link relative to the archive root, like a post's permalink, which
+/// is already encoded. Absolute if the archive has a base URL.
+pub fn archive_url(base_url: &str, href: &str) -> String {
+    if base_url.is_empty() {
+        href.to_string()
+    } else {
+        format!("{base_url}/{href}")
+    }
+}
+
+/// Link to a page of a subreddit index, relative to the archive root. Indexes
+/// in other orders than newest first live in a subdirectory `dir`.
+pub fn subreddit_page_href(sub: &str, dir: &str, page: usize) -> String {
+    let dir = if dir.is_empty() { format!("r/{sub}") } else { format!("r/{sub}/{dir}") };
+    if page <= 1 {
+        format!("{dir}/index.html")
+    } else {
+        format!("{dir}/{page}.html")
+    }
+}
+
+/// Path of a page of a subreddit index, relative to the archive root
+pub fn subreddit_page_path(sub: &str, dir: &str, page: usize) -> PathBuf {
+    PathBuf::from(subreddit_page_href(sub, dir, page))
+}
+
+/// Link to a page of an author's listing, relative to the archive root. Pages
+/// after the first live in a directory named after the author.
+pub fn user_page_href(name: &str, page: usize) -> String {
+    if page <= 1 {
+        format!("user/{name}.html")
+    } else {
+        format!("user/{name}/{page}.html")
+    }
+}
+
+/// Path of a page of an author's listing, relative to the archive root
+pub fn user_page_path(name: &str, page: usize) -> PathBuf {
+    PathBuf::from(user_page_href(name, page))
+}
+
+/// Directory of a post's comment pages, relative to the archive root, given
+/// the post's page. It mirrors the page rather than being keyed by the post's
+/// ID, which several inputs can hold.
+pub fn comment_dir_path(page: &Path) -> PathBuf {
+    Path::new(COMMENTS_DIR).join(page.with_extension(""))
+}
+
+/// Link to the directory of a post's comment pages, relative to the archive
+/// root, see `comment_dir_path()`
+pub fn comment_dir_href(page: &Path) -> String {
+    format!("{}/", page_url("", &comment_dir_path(page)))
+}
+
+/// Link to a comment's permalink page, relative to the archive root
+pub fn comment_page_href(page: &Path, comment_id: &str) -> String {
+    page_url("", &comment_page_path(page, comment_id))
+}
+
+/// Path of a comment's permalink page, relative to the archive root
+pub fn comment_page_path(page: &Path, comment_id: &str) -> PathBuf {
+    comment_dir_path(page).join(format!("{comment_id}.html"))
+}
+
+/// Apply `f` to every item on `workers` threads, reporting progress as a
+/// running count. Results are returned in the order of `items`.
+pub fn parallel_map<T, R, F>(label: &str, items: &[T], workers: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let progress = Progress::new(label, items.len());
+    let results = Mutex::new(Vec::with_capacity(items.len()));
+
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, items.len().max(1)) {
+            scope.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+                    done.push((i, f(item)));
+                    progress.inc();
+                }
+                results.lock().unwrap().extend(done);
+            });
         }
-        let entry = entry?;
-        let ty = entry.file_type()?;
-        if ty.is_dir() {
-            copy_dir_all(entry.path(), dst.as_ref().join(entry.file_name()))?;
-        } else {
-            copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Running count of finished items, printed on a single line
+struct Progress<'a> {
+    label: &'a str,
+    total: usize,
+    done: AtomicUsize,
+}
+
+impl<'a> Progress<'a> {
+    fn new(label: &'a str, total: usize) -> Self {
+        if total > 0 {
+            print!("{label}: 0/{total}");
+            _ = io::stdout().flush();
+        }
+        Self {
+            label,
+            total,
+            done: AtomicUsize::new(0),
+        }
+    }
+
+    fn inc(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if done == self.total {
+            println!("\r{}: {done}/{}", self.label, self.total);
+        } else if done % 100 == 0 {
+            print!("\r{}: {done}/{}", self.label, self.total);
+            _ = io::stdout().flush();
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_paths() {
+        let inputs: Vec<PathBuf> = ["rust/a.json", "golang/a.json", "rust/A.json", "index.json", "r/rust/index.json", "static/x.json", "comments/x.json", "comments.csv"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let paths = output_paths(&inputs, "static", false);
+        assert_eq!(paths, vec![
+            PathBuf::from("rust/a.html"),
+            PathBuf::from("golang/a.html"),
+            PathBuf::from("rust/A-2.html"),
+            PathBuf::from("index-2.html"),
+            PathBuf::from("_r/rust/index.html"),
+            PathBuf::from("_static/x.html"),
+            PathBuf::from("_comments/x.html"),
+            PathBuf::from("comments-2.html"),
+        ]);
+
+        // BDFR names files after the post's title, which links must not cut short
+        let paths = output_paths(&[PathBuf::from("rust/ferris_Is this #1? 100%_1fjx2k9.json")], "static", false);
+        assert_eq!(paths[0], PathBuf::from("rust/ferris_Is this #1? 100%_1fjx2k9.html"));
+        assert_eq!(page_url("", &paths[0]), "rust/ferris_Is%20this%20%231%3F%20100%25_1fjx2k9.html");
 
-    Ok(())
+        // The posts of an input that holds several go to a directory that no
+        // other input is in
+        let inputs: Vec<PathBuf> = ["x/rust.json", "x/rust/abc123.json", "x/Go.json", "x/go/y/z.json", "x/js.json"].iter().map(PathBuf::from).collect();
+        assert_eq!(output_paths(&inputs, "static", true), vec![
+            PathBuf::from("x/rust-2.html"),
+            PathBuf::from("x/rust/abc123.html"),
+            PathBuf::from("x/Go-2.html"),
+            PathBuf::from("x/go/y/z.html"),
+            PathBuf::from("x/js.html"),
+        ]);
+        assert_eq!(multi_post_path(&output_paths(&inputs, "static", true)[0], "abc123"), PathBuf::from("x/rust-2/abc123.html"));
+        assert_eq!(output_paths(&inputs, "static", false)[0], PathBuf::from("x/rust.html"));
+    }
+
+    #[test]
+    fn test_parallel_map() {
+        let items: Vec<usize> = (0..1000).collect();
+        assert_eq!(parallel_map("Squaring", &items, 8, |i| i * i), items.iter().map(|i| i * i).collect::<Vec<_>>());
+        assert!(parallel_map("Nothing", &[] as &[usize], 8, |i| *i).is_empty());
+    }
+
+    #[test]
+    fn test_archive_root() {
+        assert_eq!(archive_root(Path::new("index.html")), "./");
+        assert_eq!(archive_root(Path::new("r/rust/2.html")), "../../");
+        assert_eq!(multi_post_path(Path::new("scraped/rust.html"), "abc123"), PathBuf::from("scraped/rust/abc123.html"));
+        let mut names = PostNames::default();
+        assert_eq!(["abc", "def", "abc", "ABC", "abc-2"].map(|id| names.name(id)), ["abc", "def", "abc-2", "ABC-3", "abc-2-2"]);
+    }
+
+    #[test]
+    fn test_page_url() {
+        assert_eq!(page_url("", Path::new("r/rust/2.html")), "r/rust/2.html");
+        assert_eq!(page_url("https://example.com/archive", Path::new("my posts/#1 café.html")), "https://example.com/archive/my%20posts/%231%20caf%C3%A9.html");
+        assert_eq!(archive_url("https://example.com", "a%20b.html"), "https://example.com/a%20b.html");
+
+        // Comment pages are kept apart by the page of their post
+        assert_eq!(comment_page_path(Path::new("rust/self_post.html"), "c1"), PathBuf::from("comments/rust/self_post/c1.html"));
+        assert_eq!(comment_page_href(Path::new("go lang/#1.html"), "c1"), "comments/go%20lang/%231/c1.html");
+        assert_eq!(comment_dir_href(Path::new("scraped/rust/abc123.html")), "comments/scraped/rust/abc123/");
+    }
 }
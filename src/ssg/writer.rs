@@ -1,45 +1,176 @@
 #![forbid(unsafe_code)]
 #![allow(clippy::cmp_owned)]
 
-use std::collections::HashMap;
+use std::collections::BTreeSet;
 use std::error::Error;
-use std::fs::{canonicalize, File};
+use std::fs::{copy, create_dir_all, write, File};
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 
 use rinja::Template;
+use rust_embed::RustEmbed;
 
 use crate::subreddit::SubredditTemplate;
+use crate::user::UserTemplate;
 use crate::{
     post::PostTemplate,
-    ssg::util::copy_dir_all,
+    ssg::browse::BrowseTemplate,
+    ssg::search::{SearchIndex, SearchTemplate, SEARCH_INDEX_FILE, SEARCH_PAGE_FILE},
+    ssg::template::DirectoryTemplate,
+    ssg::util::parallel_map,
+    utils::ThemeAssets,
 };
 
-pub async fn write_all(
-    templates: HashMap<PathBuf, PostTemplate>,
-    index: SubredditTemplate,
+/// Static files shared by every page, except for themes (see `ThemeAssets`)
+#[derive(RustEmbed)]
+#[folder = "static/"]
+#[exclude = "themes/*"]
+pub struct StaticAssets;
+
+/// Every page and data file of a static archive, ready to be written
+pub struct Site {
+    /// Post pages and comment permalink pages, keyed by their output path
+    pub posts: Vec<(PathBuf, PostTemplate)>,
+    /// Subreddit index pages, keyed by their path relative to the archive root
+    pub indexes: Vec<(PathBuf, SubredditTemplate)>,
+    /// Date and flair pages, keyed by their path relative to the archive root
+    pub browse: Vec<(PathBuf, BrowseTemplate)>,
+    /// Author pages, keyed by their path relative to the archive root
+    pub users: Vec<(PathBuf, UserTemplate)>,
+    pub directory: DirectoryTemplate,
+    pub search_index: SearchIndex,
+    pub search_page: SearchTemplate,
+    /// Feeds, sitemaps and other generated files, keyed by their path
+    /// relative to the archive root
+    pub files: Vec<(PathBuf, String)>,
+    /// Media to copy from the source tree, with its path relative to the archive root
+    pub media: Vec<(PathBuf, PathBuf)>,
+    /// Whether static files have to be (re)copied
+    pub copy_static: bool,
+}
+
+pub fn write_all(
+    site: Site,
     out_dir: impl AsRef<Path>,
     static_path: impl AsRef<Path>,
+    theme: &str,
+    workers: usize,
 ) -> Result<(), Box<dyn Error>> {
     println!("Writing index...");
-    index.write_into(&mut File::create(out_dir.as_ref().join("index.html"))?)?;
+    write_page(&out_dir.as_ref().join("index.html"), &site.directory)?;
+
+    write_pages("Writing subreddit indexes", &site.indexes, out_dir.as_ref(), workers)?;
+    write_pages("Writing date and flair pages", &site.browse, out_dir.as_ref(), workers)?;
+    write_pages("Writing author pages", &site.users, out_dir.as_ref(), workers)?;
 
-    println!("Writing post templates...");
-    write_templates(templates).await?;
+    println!("Writing search index...");
+    write_search(&site.search_index, &site.search_page, out_dir.as_ref())?;
+
+    println!("Writing feeds and sitemaps...");
+    write_files(site.files, out_dir.as_ref())?;
+
+    write_templates(&site.posts, workers)?;
+    copy_media(&site.media, out_dir.as_ref(), workers)?;
+
+    if site.copy_static {
+        println!("Copying static files...");
+        copy_static(out_dir.as_ref().join(static_path), theme)?;
+    }
+    Ok(())
+}
 
-    println!("Copying static files...");
-    copy_static(out_dir.as_ref().join(static_path)).await?;
+/// Render a template into a file
+fn write_page(path: &Path, tmpl: &impl Template) -> Result<(), Box<dyn Error>> {
+    let mut file = BufWriter::new(File::create(path)?);
+    tmpl.write_into(&mut file)?;
+    file.flush()?;
     Ok(())
 }
 
+/// Collect the errors of parallel work, keeping the first one
+fn first_error(results: Vec<Result<(), String>>) -> Result<(), Box<dyn Error>> {
+    match results.into_iter().find_map(Result::err) {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
 /// Write templates to HTML files
-pub async fn write_templates(templates: HashMap<PathBuf, PostTemplate>) -> Result<(), Box<dyn Error>> {
-    for (path, tmpl) in templates {
-        tmpl.write_into(&mut File::create(path)?)?;
+pub fn write_templates(templates: &[(PathBuf, PostTemplate)], workers: usize) -> Result<(), Box<dyn Error>> {
+    for dir in templates.iter().filter_map(|(path, _)| path.parent()).collect::<BTreeSet<_>>() {
+        create_dir_all(dir)?;
+    }
+    first_error(parallel_map("Writing post templates", templates, workers, |(path, tmpl)| {
+        write_page(path, tmpl).map_err(|e| format!("{}: {e}", path.display()))
+    }))
+}
+
+/// Write index pages, keyed by their path relative to `out_dir`
+pub fn write_pages<T: Template + Sync>(
+    label: &str,
+    pages: &[(PathBuf, T)],
+    out_dir: &Path,
+    workers: usize,
+) -> Result<(), Box<dyn Error>> {
+    for path in pages.iter().filter_map(|(path, _)| path.parent()).collect::<BTreeSet<_>>() {
+        create_dir_all(out_dir.join(path))?;
+    }
+    first_error(parallel_map(label, pages, workers, |(path, tmpl)| {
+        write_page(&out_dir.join(path), tmpl).map_err(|e| format!("{}: {e}", path.display()))
+    }))
+}
+
+/// Write generated files, keyed by their path relative to `out_dir`
+pub fn write_files(files: Vec<(PathBuf, String)>, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+    for (path, contents) in files {
+        let path = out_dir.join(path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(path, contents)?;
     }
     Ok(())
 }
 
-/// Copy static files to the site output directory
-pub async fn copy_static(out_dir: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
-    Ok(copy_dir_all(canonicalize("../../static")?, out_dir)?)
-}
\ No newline at end of file
+/// Write the prebuilt search index and the static search page
+pub fn write_search(index: &SearchIndex, page: &SearchTemplate, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+    write(out_dir.join(SEARCH_INDEX_FILE), index.to_script()?)?;
+    write_page(&out_dir.join(SEARCH_PAGE_FILE), page)
+}
+
+/// Copy media files into the archive
+pub fn copy_media(media: &[(PathBuf, PathBuf)], out_dir: &Path, workers: usize) -> Result<(), Box<dyn Error>> {
+    for dest in media.iter().filter_map(|(_, dest)| dest.parent()).collect::<BTreeSet<_>>() {
+        create_dir_all(out_dir.join(dest))?;
+    }
+    first_error(parallel_map("Copying media", media, workers, |(src, dest)| {
+        copy(src, out_dir.join(dest)).map(|_| ()).map_err(|e| format!("{}: {e}", src.display()))
+    }))
+}
+
+/// Write the embedded static files to the site output directory, with a
+/// theme appended to the stylesheet
+pub fn copy_static(out_dir: impl AsRef<Path>, theme: &str) -> Result<(), Box<dyn Error>> {
+    create_dir_all(&out_dir)?;
+
+    for file in StaticAssets::iter() {
+        let asset = StaticAssets::get(&file).ok_or_else(|| format!("missing static file {file}"))?;
+        let path = out_dir.as_ref().join(file.as_ref());
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        if file == "style.css" {
+            let mut style = asset.data.into_owned();
+            if let Some(theme) = ThemeAssets::get(&format!("{theme}.css")) {
+                style.push(b'\n');
+                style.extend_from_slice(&theme.data);
+            }
+            write(path, style)?;
+        } else {
+            write(path, asset.data)?;
+        }
+    }
+
+    Ok(())
+}
@@ -34,6 +34,10 @@ pub struct SubredditTemplate {
 	/// Whether all posts were hidden because they are NSFW (and user has disabled show NSFW)
 	pub all_posts_hidden_nsfw: bool,
 	pub no_posts: bool,
+	/// Sorts of a static archive index and the pages they link to. Empty when served live.
+	pub sort_links: Vec<(String, String)>,
+	/// Timeframes of a static archive index sorted by top and the pages they link to
+	pub timeframe_links: Vec<(String, String)>,
 }
 
 #[derive(Template)]
@@ -144,6 +148,8 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 			all_posts_filtered: false,
 			all_posts_hidden_nsfw: false,
 			no_posts: false,
+			sort_links: Vec::new(),
+			timeframe_links: Vec::new(),
 		}))
 	} else {
 		match Post::fetch(&path, quarantined).await {
@@ -167,6 +173,8 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 					all_posts_filtered,
 					all_posts_hidden_nsfw,
 					no_posts,
+					sort_links: Vec::new(),
+					timeframe_links: Vec::new(),
 				}))
 			}
 			Err(msg) => match msg.as_str() {
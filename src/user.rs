@@ -12,24 +12,24 @@ use time::{macros::format_description, OffsetDateTime};
 // STRUCTS
 #[derive(Template)]
 #[template(path = "user.html")]
-struct UserTemplate {
-	user: User,
-	posts: Vec<Post>,
-	sort: (String, String),
-	ends: (String, String),
+pub struct UserTemplate {
+	pub user: User,
+	pub posts: Vec<Post>,
+	pub sort: (String, String),
+	pub ends: (String, String),
 	/// "overview", "comments", or "submitted"
-	listing: String,
-	prefs: Preferences,
-	url: String,
-	redirect_url: String,
+	pub listing: String,
+	pub prefs: Preferences,
+	pub url: String,
+	pub redirect_url: String,
 	/// Whether the user themself is filtered.
-	is_filtered: bool,
+	pub is_filtered: bool,
 	/// Whether all fetched posts are filtered (to differentiate between no posts fetched in the first place,
 	/// and all fetched posts being filtered).
-	all_posts_filtered: bool,
+	pub all_posts_filtered: bool,
 	/// Whether all posts were hidden because they are NSFW (and user has disabled show NSFW)
-	all_posts_hidden_nsfw: bool,
-	no_posts: bool,
+	pub all_posts_hidden_nsfw: bool,
+	pub no_posts: bool,
 }
 
 // FUNCTIONS
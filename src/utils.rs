@@ -13,7 +13,7 @@ use once_cell::sync::Lazy;
 use regex::Regex;
 use rinja::Template;
 use rust_embed::RustEmbed;
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Value;
 use serde_json_path::{JsonPath, JsonPathExt};
 use std::collections::{HashMap, HashSet};
@@ -49,7 +49,7 @@ pub enum ResourceType {
 }
 
 // Post flair with content, background color and foreground color
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Flair {
 	pub flair_parts: Vec<FlairPart>,
 	pub text: String,
@@ -58,7 +58,7 @@ pub struct Flair {
 }
 
 // Part of flair, either emoji or text
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct FlairPart {
 	pub flair_part_type: String,
 	pub value: String,
@@ -111,7 +111,7 @@ impl Default for Flair {
 	}
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Author {
 	pub name: String,
 	pub flair: Flair,
@@ -128,7 +128,7 @@ impl Default for Author {
 	}
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Poll {
 	pub poll_options: Vec<PollOption>,
 	pub voting_end_timestamp: (String, String),
@@ -156,7 +156,7 @@ impl Poll {
 	}
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PollOption {
 	pub id: u64,
 	pub text: String,
@@ -186,14 +186,17 @@ impl PollOption {
 }
 
 // Post flags with nsfw and stickied
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Flags {
 	pub spoiler: bool,
 	pub nsfw: bool,
 	pub stickied: bool,
+	/// Whether new comments are disabled
+	#[serde(default)]
+	pub locked: bool,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Media {
 	pub url: String,
 	pub alt_url: String,
@@ -293,7 +296,7 @@ impl Media {
 	}
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GalleryMedia {
 	pub url: String,
 	pub width: i64,
@@ -334,7 +337,7 @@ impl GalleryMedia {
 }
 
 // Post containing content, metadata and media
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Post {
 	pub id: String,
 	pub title: String,
@@ -458,6 +461,7 @@ impl Post {
 					spoiler: data["spoiler"].as_bool().unwrap_or_default(),
 					nsfw: data["over_18"].as_bool().unwrap_or_default(),
 					stickied: data["stickied"].as_bool().unwrap_or_default() || data["pinned"].as_bool().unwrap_or_default(),
+					locked: data["locked"].as_bool().unwrap_or_default(),
 				},
 				permalink: val(post, "permalink"),
 				link_title: val(post, "link_title"),
@@ -478,7 +482,7 @@ impl Post {
 	}
 }
 
-#[derive(Template)]
+#[derive(Clone, Template)]
 #[template(path = "comment.html")]
 // Comment with content, post, score and data/time that it was posted
 pub struct Comment {
@@ -496,6 +500,8 @@ pub struct Comment {
 	pub rel_time: String,
 	/// Date comment was posted
 	pub created: String,
+	/// Unix timestamp of when the comment was posted
+	pub created_ts: u64,
 	pub edited: (String, String),
 	pub replies: Vec<Comment>,
 	pub highlighted: bool,
@@ -506,7 +512,7 @@ pub struct Comment {
 	pub prefs: Preferences,
 }
 
-#[derive(Default, Clone, Serialize)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Award {
 	pub name: String,
 	pub icon_url: String,
@@ -653,6 +659,14 @@ pub struct Preferences {
 	pub hide_score: String,
 	#[serde(skip)]
 	pub static_path: String,
+	/// Relative path from the current page to the root of a static archive
+	/// generated by `ssg`. Empty when pages are served live.
+	#[serde(skip)]
+	pub archive_root: String,
+	/// Absolute URL of a static archive, if it was given one. Feeds are only
+	/// generated for archives with a base URL.
+	#[serde(skip)]
+	pub archive_base_url: String,
 }
 
 impl Default for Preferences {
@@ -680,6 +694,8 @@ impl Default for Preferences {
 			hide_awards: "off".to_string(),
 			hide_score: "off".to_string(),
 			static_path: "".to_string(),
+			archive_root: String::new(),
+			archive_base_url: String::new(),
 		}
 	}
 }
@@ -733,6 +749,8 @@ impl Preferences {
 			hide_awards: setting(req, "hide_awards"),
 			hide_score: setting(req, "hide_score"),
 			static_path: "".to_string(),
+			archive_root: String::new(),
+			archive_base_url: String::new(),
 		}
 	}
 
@@ -847,6 +865,7 @@ pub async fn parse_post(post: &Value) -> Post {
 			spoiler: post["data"]["spoiler"].as_bool().unwrap_or_default(),
 			nsfw: post["data"]["over_18"].as_bool().unwrap_or_default(),
 			stickied: post["data"]["stickied"].as_bool().unwrap_or_default() || post["data"]["pinned"].as_bool().unwrap_or(false),
+			locked: post["data"]["locked"].as_bool().unwrap_or_default(),
 		},
 		domain: val(post, "domain"),
 		rel_time,
@@ -1452,6 +1471,8 @@ mod tests {
 			hide_awards: "off".to_owned(),
 			hide_score: "off".to_owned(),
 			static_path: "./static".to_owned(),
+			archive_root: String::new(),
+			archive_base_url: String::new(),
 		};
 		let urlencoded = serde_urlencoded::to_string(prefs).expect("Failed to serialize Prefs");
 
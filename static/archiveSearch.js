@@ -0,0 +1,116 @@
+// Search a static archive using the prebuilt index in search-index.js.
+(function () {
+	'use strict';
+
+	const MIN_TOKEN_LEN = 2;
+	const MAX_TOKEN_LEN = 32;
+	const MAX_RESULTS = 100;
+
+	// Must match TOKEN_SEPARATOR in src/ssg/search.rs
+	const TOKEN_SEPARATOR = /[^\p{L}\p{M}\p{N}_]+/u;
+
+	// Must match tokenize() in src/ssg/search.rs
+	function tokenize(text) {
+		return text
+			.split(TOKEN_SEPARATOR)
+			.filter((token) => {
+				const len = Array.from(token).length;
+				return len >= MIN_TOKEN_LEN && len <= MAX_TOKEN_LEN;
+			})
+			.map((token) => token.toLowerCase());
+	}
+
+	// Positions of the documents containing a term. The last term of a query
+	// also matches as a prefix, so results show up while typing.
+	function postings(index, term, prefix) {
+		if (!prefix) {
+			return index.terms[term] || [];
+		}
+		const docs = new Set();
+		for (const key in index.terms) {
+			if (key.startsWith(term)) {
+				index.terms[key].forEach((doc) => docs.add(doc));
+			}
+		}
+		return Array.from(docs).sort((a, b) => a - b);
+	}
+
+	function search(index, query, sub) {
+		const terms = tokenize(query);
+		if (terms.length === 0) {
+			return [];
+		}
+
+		let matches = null;
+		terms.forEach((term, i) => {
+			const docs = new Set(postings(index, term, i === terms.length - 1));
+			matches = matches === null ? docs : new Set([...matches].filter((doc) => docs.has(doc)));
+		});
+
+		return [...matches]
+			.sort((a, b) => a - b)
+			.map((doc) => index.docs[doc])
+			.filter((doc) => !sub || doc.s.toLowerCase() === sub.toLowerCase());
+	}
+
+	function element(tag, className, text) {
+		const el = document.createElement(tag);
+		if (className) el.className = className;
+		if (text) el.textContent = text;
+		return el;
+	}
+
+	function render(results, container) {
+		container.replaceChildren();
+		results.slice(0, MAX_RESULTS).forEach((doc) => {
+			const post = element('div', 'post');
+
+			const header = element('p', 'post_header');
+			const community = element('a', 'post_subreddit', 'r/' + doc.s);
+			community.href = 'r/' + doc.s + '/index.html';
+			header.append(community, element('span', 'dot', '•'), element('span', 'post_author', 'u/' + doc.a));
+			header.append(element('span', 'dot', '•'), element('span', 'created', doc.d));
+
+			const title = element('h2', 'post_title');
+			if (doc.f) {
+				title.append(element('span', 'post_flair', doc.f), ' ');
+			}
+			const link = element('a', null, doc.t);
+			link.href = doc.u;
+			title.append(link);
+
+			post.append(header, title);
+			container.append(element('hr', 'sep'), post);
+		});
+	}
+
+	const params = new URLSearchParams(window.location.search);
+	const query = params.get('q') || '';
+	const sub = params.get('restrict_sr') ? params.get('sub') || '' : '';
+
+	document.getElementById('search').value = query;
+	if (params.get('sub')) {
+		document.getElementById('inside').hidden = false;
+		document.getElementById('restrict_sr').checked = !!sub;
+		document.getElementById('restrict_sub').value = params.get('sub');
+		document.querySelector('#inside .search_label').textContent = 'in r/' + params.get('sub');
+	}
+
+	const status = document.getElementById('search_status');
+	const index = window.ARCHIVE_SEARCH_INDEX;
+	if (!index) {
+		status.textContent = 'The search index could not be loaded.';
+		return;
+	}
+	if (!query) {
+		return;
+	}
+
+	const results = search(index, query, sub);
+	status.textContent = results.length === 0
+		? 'No posts were found.'
+		: results.length > MAX_RESULTS
+			? 'Showing ' + MAX_RESULTS + ' of ' + results.length + ' posts.'
+			: results.length + (results.length === 1 ? ' post' : ' posts') + ' found.';
+	render(results, document.getElementById('posts'));
+})();